@@ -0,0 +1,297 @@
+use std::collections::HashSet;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::coin::Coin;
+use crate::leader_schedule::{LeaderSchedule, ReputationConfig};
+use crate::ordering::{DagView, OrderingEngine};
+use crate::{CommittedSubDag, Hash, ValidatorId, ValidatorSet};
+
+/*
+    Bullshark (partially synchronous)
+
+    Runs over the same DAG as Tusk but is built for a mostly synchronous network:
+    - every even round has an anchor, the steady state leader is known ahead of time
+    - validators wait (up to a timeout) for the anchor before leaving an even round,
+      and for its votes before leaving the odd round after it
+    - the anchor commits as soon as enough certified blocks in the next round point at it
+      (no waiting for the coin two rounds later like Tusk)
+
+    With the fallback on, a next round block that doesn't point at the steady
+    anchor is a fallback vote - its author timed out on the anchor or never saw it.
+    That's the asynchronous path: the coin leader of the same round (revealed two
+    rounds later, like a tusk wave) takes the slot instead, and an adversary can't
+    target a leader it can't predict so we keep committing even when the steady
+    leaders keep getting knocked out. The votes are fixed by the blocks so every
+    node counts the same ones:
+    - direct commit needs 2f+1 votes of one kind (steady, or fallback plus f+1
+      votes for the coin leader) - never both kinds for the same slot
+    - walking back from a committed anchor, a slot is steady if f+1 steady votes
+      are in that anchor's causal history. If the slot committed steady somewhere
+      the 2f+1 votes leave at least f+1 in any history, if it committed through
+      the fallback there are at most f steady votes anywhere
+    Without the fallback the steady anchor is the only leader and f+1 votes do.
+    Steady leaders follow the reputation schedule (leader_schedule.rs)
+*/
+
+#[derive(Debug, Clone)]
+pub struct BullsharkConfig {
+    // how long we wait on the anchor (or its votes) before moving on anyway
+    pub leader_timeout: Duration,
+    // let the coin take slots whose steady anchor timed out - costs the steady path its f+1 rule (steady_votes_needed),
+        // so it's off unless asked for
+    pub fallback: bool,
+    // steady leaders come from the reputation schedule instead of plain round robin
+    pub reputation: ReputationConfig,
+}
+
+impl Default for BullsharkConfig {
+    fn default() -> Self {
+        Self {
+            leader_timeout: Duration::from_millis(1000),
+            fallback: false,
+            reputation: ReputationConfig::default(),
+        }
+    }
+}
+
+impl BullsharkConfig {
+    /*
+        Votes a steady anchor needs to commit directly. f+1 is only safe while the
+        steady anchor is the sole leader - with the fallback on, f+1 steady votes at
+        one node and 2f+1 fallback votes at another could both happen for the same
+        slot, so the steady path needs a quorum too. Turn the fallback off for the
+        plain f+1 rule
+     */
+    pub fn steady_votes_needed(&self, validator_set: &ValidatorSet) -> usize {
+        if self.fallback {
+            validator_set.threshold
+        } else {
+            validator_set.validity_threshold()
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AnchorKind {
+    // from the schedule, known in advance
+    Steady,
+    // revealed by the coin two rounds later
+    Fallback,
+}
+
+// next round blocks for an anchor slot, by whether they point at the steady anchor
+#[derive(Debug, Default)]
+struct Votes {
+    steady: usize,
+    fallback: usize,
+    // points at a block we don't have yet - could be either
+    unsure: usize,
+}
+
+pub struct Bullshark {
+    config: BullsharkConfig,
+    coin: Arc<Coin>,
+    schedule: LeaderSchedule,
+    // round of the last anchor we committed
+    last_anchor_round: Option<u32>,
+}
+
+impl Bullshark {
+    pub fn new(config: BullsharkConfig, coin: Arc<Coin>, validator_set: &ValidatorSet) -> Self {
+        Self {
+            schedule: LeaderSchedule::new(validator_set, config.reputation.clone()),
+            config,
+            coin,
+            last_anchor_round: None,
+        }
+    }
+
+    // rotate per anchor slot (round / 2) or half the validators never lead
+    pub fn steady_leader(&self, round: u32) -> ValidatorId {
+        self.schedule.leader((round / 2) as u64)
+    }
+
+    // same slot as the tusk wave starting at this round - shares ride on round + 2
+    pub fn fallback_leader(&self, view: &DagView, round: u32) -> Option<ValidatorId> {
+        let value = self.coin.wave_value(round / 2, round, &view.coin_shares(round + 2))?;
+        Some(self.schedule.leader(value))
+    }
+
+    // who leads the anchor slot - None for a coin slot whose coin isn't revealed yet
+    pub fn anchor_leader(&self, view: &DagView, round: u32, kind: AnchorKind) -> Option<ValidatorId> {
+        match kind {
+            AnchorKind::Steady => Some(self.steady_leader(round)),
+            AnchorKind::Fallback => self.fallback_leader(view, round),
+        }
+    }
+
+    // certified anchor block for an even round, if we know the leader and have its block
+    pub fn anchor_block(&self, view: &DagView, round: u32, kind: AnchorKind) -> Option<Hash> {
+        let leader = self.anchor_leader(view, round, kind)?;
+        view.certified_block(leader, round)
+    }
+
+    // one vote per author among the certified round + 1 blocks in `voters`
+    fn tally(&self, view: &DagView, round: u32, voters: impl IntoIterator<Item = Hash>) -> Votes {
+        let leader = self.steady_leader(round);
+        let mut authors = HashSet::new();
+        let mut votes = Votes::default();
+
+        for hash in voters {
+            let Some(block) = view.dag.get_block(&hash) else {
+                continue;
+            };
+            if block.round != round + 1 || !view.is_certified(&hash) || !authors.insert(block.author) {
+                continue;
+            }
+
+            let parents: Vec<_> = block.parents.iter().map(|parent| view.dag.get_block(parent)).collect();
+            if parents.iter().flatten().any(|parent| parent.author == leader) {
+                votes.steady += 1;
+            } else if parents.iter().any(Option::is_none) {
+                votes.unsure += 1;
+            } else {
+                votes.fallback += 1;
+            }
+        }
+        votes
+    }
+
+    // anchor of the slot that commits on its own votes, if any
+    fn direct_anchor(&self, view: &DagView, round: u32) -> Option<Hash> {
+        let votes_needed = self.config.steady_votes_needed(view.validator_set);
+        if !self.config.fallback {
+            return self.anchor_block(view, round, AnchorKind::Steady)
+                .filter(|anchor| view.support(anchor) >= votes_needed);
+        }
+
+        let votes = self.tally(view, round, view.dag.get_round_blocks(round + 1));
+        if votes.steady >= votes_needed {
+            self.anchor_block(view, round, AnchorKind::Steady)
+        } else if votes.fallback >= view.validator_set.threshold {
+            self.anchor_block(view, round, AnchorKind::Fallback)
+                .filter(|anchor| view.support(anchor) >= view.validator_set.validity_threshold())
+        } else {
+            None
+        }
+    }
+
+    // which kind the slot has as far as `history` (an anchor's causal history) goes - None if we can't tell yet
+    fn kind_in_history(&self, view: &DagView, round: u32, history: &HashSet<Hash>) -> Option<AnchorKind> {
+        if !self.config.fallback {
+            return Some(AnchorKind::Steady);
+        }
+
+        let voters = view.dag.get_round_blocks(round + 1).into_iter().filter(|hash| history.contains(hash));
+        let votes = self.tally(view, round, voters);
+        let votes_needed = view.validator_set.validity_threshold();
+        if votes.steady >= votes_needed {
+            Some(AnchorKind::Steady)
+        } else if votes.steady + votes.unsure >= votes_needed {
+            None
+        } else {
+            Some(AnchorKind::Fallback)
+        }
+    }
+
+    /*
+        Anchors to commit right now, oldest first. Looks for the newest anchor
+        with enough votes, then walks back through the uncommitted slots ordering
+        every older anchor it has a path to
+    */
+    pub fn ordered_anchors(&self, view: &DagView) -> Vec<(u32, Hash)> {
+        // votes for an anchor live in the round after it
+        if view.current_round < 1 {
+            return Vec::new();
+        }
+        let newest = (view.current_round - 1) & !1;
+        let first_open = self.last_anchor_round.map_or(0, |last| last + 2);
+        if newest < first_open {
+            return Vec::new();
+        }
+
+        let direct = (first_open..=newest).rev().step_by(2)
+            .find_map(|round| self.direct_anchor(view, round).map(|anchor| (round, anchor)));
+        let Some((anchor_round, anchor)) = direct else {
+            return Vec::new();
+        };
+
+        let mut anchors = vec![(anchor_round, anchor)];
+        let mut current = anchor;
+        let mut history = HashSet::new();
+        view.dag.extend_history_above(&mut history, [current], first_open);
+        for round in (first_open..anchor_round).step_by(2).rev() {
+            // can't skip a slot we can't decide yet (coin not in, votes missing) - another node might order it
+                // try again once they show up
+            let Some(leader) = self.kind_in_history(view, round, &history)
+                .and_then(|kind| self.anchor_leader(view, round, kind)) else {
+                return Vec::new();
+            };
+
+            if let Some(prev) = view.certified_block(leader, round)
+                && view.dag.check_path(&prev, &current).is_some() {
+                anchors.push((round, prev));
+                current = prev;
+                // the older anchor's history is all we go by from here on
+                history.clear();
+                view.dag.extend_history_above(&mut history, [current], first_open);
+            }
+        }
+
+        anchors.reverse();
+        anchors
+    }
+}
+
+impl OrderingEngine for Bullshark {
+    fn name(&self) -> &'static str {
+        "bullshark"
+    }
+
+    fn try_commit(&mut self, view: &DagView) -> Vec<CommittedSubDag> {
+        let anchors = self.ordered_anchors(view);
+        let Some(&(newest, _)) = anchors.last() else {
+            return Vec::new();
+        };
+        self.last_anchor_round = Some(newest);
+
+        let leaders: Vec<Hash> = anchors.into_iter().map(|(_, anchor)| anchor).collect();
+        let committed = view.commit_scheduled(&leaders, &mut self.schedule);
+        // stopped early on a schedule change - the later anchors get another go
+        if let Some(last) = committed.last() {
+            self.last_anchor_round = Some(last.round);
+        }
+        committed
+    }
+
+    // the steady anchor, and the coin's pick once it's out if the fallback is on
+    fn leaders(&self, view: &DagView, round: u32) -> Vec<ValidatorId> {
+        if !round.is_multiple_of(2) {
+            return Vec::new();
+        }
+        let fallback = self.fallback_leader(view, round).filter(|_| self.config.fallback);
+        std::iter::once(self.steady_leader(round)).chain(fallback).collect()
+    }
+
+    /*
+        Leader timeout check for round advancement:
+        - even round: wait until we have the certified steady anchor
+        - odd round: wait until enough blocks vote for it to commit it directly
+        Running out the timeout instead is what makes our next block a fallback vote
+    */
+    fn leader_ready(&self, view: &DagView) -> bool {
+        let round = view.current_round;
+        let anchor_round = round & !1;
+        let Some(anchor) = view.certified_block(self.steady_leader(anchor_round), anchor_round) else {
+            return false;
+        };
+
+        let votes_needed = self.config.steady_votes_needed(view.validator_set);
+        round == anchor_round || view.support(&anchor) >= votes_needed
+    }
+
+    fn leader_timeout(&self) -> Duration {
+        self.config.leader_timeout
+    }
+}
@@ -0,0 +1,187 @@
+use std::collections::VecDeque;
+use std::str::FromStr;
+use std::sync::Mutex;
+
+use tokio::time::{sleep_until, Duration, Instant};
+
+use crate::consensus::ConsensusHandle;
+use crate::network::{MessagePayload, NetworkMsg};
+use crate::transport::Transport;
+use crate::{Block, Transaction, ValidatorId};
+
+/*
+    Byzantine behaviors for the simulator
+
+    A byzantine node runs the honest Node on top of a ByzantineTransport,
+    which bends what goes out (and sometimes what comes in) - the consensus
+    code itself never knows. Picked per node by name in
+    SimulationConfig.byzantine_nodes:
+        equivocate      - every header goes out as two different blocks, half the peers get each
+        withhold-votes  - never sends a vote
+        bad-signatures  - votes and certificates go out with garbage signatures
+        vote-everything - signs every header it sees the moment it arrives, no safety rules
+        delay-leader    - holds back everything for a leader round's leader by a second. Only
+                          bites where the leader is known in time - bullshark's steady anchor,
+                          round robin, tusk on the deterministic coin
+        silent          - crashed: sends nothing at all
+*/
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Behavior {
+    Equivocate,
+    WithholdVotes,
+    BadSignatures,
+    VoteForEverything,
+    // how long messages to the leader are held back
+    DelayLeader(Duration),
+    Silent,
+}
+
+impl FromStr for Behavior {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "equivocate" => Ok(Behavior::Equivocate),
+            "withhold-votes" => Ok(Behavior::WithholdVotes),
+            "bad-signatures" => Ok(Behavior::BadSignatures),
+            "vote-everything" => Ok(Behavior::VoteForEverything),
+            "delay-leader" => Ok(Behavior::DelayLeader(Duration::from_secs(1))),
+            "silent" => Ok(Behavior::Silent),
+            other => Err(format!("Unknown byzantine behavior {}", other)),
+        }
+    }
+}
+
+pub struct ByzantineTransport<T: Transport> {
+    inner: T,
+    id: ValidatorId,
+    behavior: Behavior,
+    // every validator, us included - we do the fan out ourselves so each peer can get something different
+    peers: Vec<ValidatorId>,
+    // the node we're wrapping - delay-leader asks its engine who leads
+    consensus: ConsensusHandle,
+    // (when it can go, message) oldest first - delay-leader holds and vote-everything's votes
+    held: Mutex<VecDeque<(Instant, NetworkMsg)>>,
+}
+
+impl<T: Transport> ByzantineTransport<T> {
+    pub fn new(inner: T, id: ValidatorId, behavior: Behavior, consensus: ConsensusHandle) -> Self {
+        let mut peers: Vec<ValidatorId> = consensus.validator_set().validators.keys().copied().collect();
+        peers.sort();
+        Self { inner, id, behavior, peers, consensus, held: Mutex::new(VecDeque::new()) }
+    }
+
+    /*
+        whoever the engine would elect for the message's round, same schedule and
+        coin as everyone else - nothing off leader rounds. With the threshold coin
+        a tusk leader isn't known until it's revealed two rounds later, by which
+        point its round is over - which is exactly why the coin is there
+     */
+    async fn leaders_of(&self, payload: &MessagePayload) -> Vec<ValidatorId> {
+        let round = match payload {
+            MessagePayload::Block(block) => block.round,
+            MessagePayload::Vote(vote) => vote.round,
+            MessagePayload::Certificate(cert) => cert.round,
+            // reliable.rs sits underneath us, we only ever see what the node sends
+            MessagePayload::FetchCertificates(_) | MessagePayload::Sequenced { .. } | MessagePayload::Ack { .. } => return Vec::new(),
+        };
+        self.consensus.leaders(round).await
+    }
+
+    // same slot, different contents - honest voters will only ever sign one of them
+    fn twin(block: &Block) -> Block {
+        let mut twin = Block::new(vec![Transaction::proposed(block.author, block.round, 0, format!("node {} equivocating", block.author))], block.parents.clone(), block.author, block.round)
+            .with_weak_parents(block.weak_parents.clone());
+        if let Some(share) = block.coin_share {
+            twin = twin.with_coin_share(share);
+        }
+        twin
+    }
+
+    fn next_release(&self) -> Option<Instant> {
+        self.held.lock().unwrap().front().map(|(at, _)| *at)
+    }
+
+    async fn release_held(&self) {
+        loop {
+            let due = {
+                let mut held = self.held.lock().unwrap();
+                match held.front() {
+                    Some((at, _)) if *at <= Instant::now() => held.pop_front().map(|(_, msg)| msg),
+                    _ => None,
+                }
+            };
+            match due {
+                Some(msg) => self.inner.send(msg).await,
+                None => return,
+            }
+        }
+    }
+}
+
+impl<T: Transport + Sync> Transport for ByzantineTransport<T> {
+    async fn send(&self, mut msg: NetworkMsg) {
+        match self.behavior {
+            Behavior::Silent => return,
+            Behavior::WithholdVotes if matches!(msg.payload, MessagePayload::Vote(_)) => return,
+            Behavior::BadSignatures => match &mut msg.payload {
+                MessagePayload::Vote(vote) => vote.signature = [0xff; 16],
+                MessagePayload::Certificate(cert) => {
+                    for (_, signature) in cert.signatures.iter_mut() {
+                        *signature = [0xff; 16];
+                    }
+                }
+                _ => {}
+            },
+            Behavior::DelayLeader(delay) if self.leaders_of(&msg.payload).await.contains(&msg.to) => {
+                self.held.lock().unwrap().push_back((Instant::now() + delay, msg));
+                return;
+            }
+            _ => {}
+        }
+        self.inner.send(msg).await;
+    }
+
+    async fn broadcast(&self, from: ValidatorId, payload: MessagePayload) {
+        let twin = match (&self.behavior, &payload) {
+            (Behavior::Equivocate, MessagePayload::Block(block)) if block.author == self.id => Some(Self::twin(block)),
+            _ => None,
+        };
+
+        let peers = self.peers.iter().filter(|&&id| id != from);
+        for (i, &to) in peers.enumerate() {
+            let payload = match &twin {
+                Some(twin) if i % 2 == 1 => MessagePayload::Block(twin.clone()),
+                _ => payload.clone(),
+            };
+            self.send(NetworkMsg { from, to, payload }).await;
+        }
+    }
+
+    // held messages go out from here since this is what the node is always waiting on.
+    // one can get lost if the node drops this mid-send - an adversary can live with that
+    async fn recv(&mut self) -> Option<NetworkMsg> {
+        loop {
+            self.release_held().await;
+
+            let msg = match self.next_release() {
+                Some(at) => tokio::select! {
+                    biased;
+                    msg = self.inner.recv() => msg,
+                    _ = sleep_until(at) => continue,
+                },
+                None => self.inner.recv().await,
+            }?;
+
+            // sign it before the node has even looked at it - queued rather than sent
+            // here, awaiting after the message is out of inner would lose it if recv gets cancelled
+            if let (Behavior::VoteForEverything, MessagePayload::Block(block)) = (&self.behavior, &msg.payload)
+                && let Some(vote) = self.consensus.sign_vote(&block.hash, block.round, self.id) {
+                let msg = NetworkMsg { from: self.id, to: block.author, payload: MessagePayload::Vote(vote) };
+                self.held.lock().unwrap().push_back((Instant::now(), msg));
+            }
+            return Some(msg);
+        }
+    }
+}
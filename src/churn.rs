@@ -0,0 +1,327 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::Arc;
+
+use tokio::task::JoinHandle;
+use tokio::time::{interval, Duration};
+
+use crate::byzantine::ByzantineTransport;
+use crate::network::{Crash, Restart, SimTransport, Simulator};
+use crate::node::Node;
+use crate::reliable::ReliableTransport;
+use crate::synchronizer::SyncConfig;
+use crate::transport::Transport;
+use crate::{ConsensusHandle, ValidatorId};
+
+/*
+    Node churn
+
+    Cluster owns every node of a simulation so it can kill and revive them:
+        - crash: the node's route is dropped (anything in flight to it is lost)
+          and its task aborted
+        - restart: a new Node on a new route, with either a brand new
+          ConsensusHandle (Restart::Fresh) or the one it went down with
+          (Restart::Persisted)
+    Crashes come from SimulationConfig.crashes and at random, node_churn per
+    node per second. Random ones never take more than f nodes down at once so
+    the run keeps its quorum - a schedule can do whatever it likes.
+
+    Every restart is followed until the node is back in the round the others
+    were in when it came up and has as many commits as they had - that's the
+    report
+*/
+
+// builds a node's consensus from nothing - used for the first start and fresh restarts
+pub type MakeConsensus = Arc<dyn Fn(ValidatorId) -> ConsensusHandle + Send + Sync>;
+
+// how often the cluster looks at the schedule and the restarted nodes
+const TICK: Duration = Duration::from_millis(20);
+
+struct Slot {
+    consensus: ConsensusHandle,
+    // None while it's down
+    task: Option<JoinHandle<()>>,
+}
+
+// how one restart went
+#[derive(Debug, Clone)]
+pub struct Recovery {
+    pub node: ValidatorId,
+    pub restart: Restart,
+    pub crashed_at: Duration,
+    pub restarted_at: Duration,
+    // where the node was when it came back
+    pub round_at_restart: u32,
+    pub commits_at_restart: u64,
+    // where the rest of the cluster was
+    pub target_round: u32,
+    pub target_commits: u64,
+    // when it got there (since simulator start) - None if it hasn't
+    pub round_caught_up: Option<Duration>,
+    pub commits_caught_up: Option<Duration>,
+}
+
+impl Recovery {
+    pub fn caught_up(&self) -> bool {
+        self.round_caught_up.is_some() && self.commits_caught_up.is_some()
+    }
+}
+
+impl fmt::Display for Recovery {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let after = |at: Option<Duration>| match at {
+            Some(at) => format!("after {:?}", at.saturating_sub(self.restarted_at)),
+            None => "never".to_string(),
+        };
+        write!(
+            f,
+            "node {} down {:?} ({:?} restart): came back in round {} with {} commits, others at round {} with {} - round caught up {}, commits caught up {}",
+            self.node,
+            self.restarted_at.saturating_sub(self.crashed_at),
+            self.restart,
+            self.round_at_restart,
+            self.commits_at_restart,
+            self.target_round,
+            self.target_commits,
+            after(self.round_caught_up),
+            after(self.commits_caught_up),
+        )
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ChurnReport {
+    pub recoveries: Vec<Recovery>,
+    // down when the report was taken
+    pub still_down: Vec<ValidatorId>,
+}
+
+impl fmt::Display for ChurnReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{} restarts", self.recoveries.len())?;
+        for recovery in &self.recoveries {
+            writeln!(f, "  {}", recovery)?;
+        }
+        if !self.still_down.is_empty() {
+            writeln!(f, "  still down: {:?}", self.still_down)?;
+        }
+        Ok(())
+    }
+}
+
+pub struct Cluster {
+    sim: Simulator,
+    make_consensus: MakeConsensus,
+    sync: SyncConfig,
+    nodes: BTreeMap<ValidatorId, Slot>,
+    down_since: BTreeMap<ValidatorId, Duration>,
+    // scheduled crashes that haven't happened yet
+    crashes: Vec<Crash>,
+    // (node, when, how)
+    restarts: Vec<(ValidatorId, Duration, Restart)>,
+    recoveries: Vec<Recovery>,
+}
+
+impl Cluster {
+    pub fn new(sim: Simulator, make_consensus: MakeConsensus) -> Self {
+        let crashes = sim.config().crashes.clone();
+        Self {
+            sim,
+            make_consensus,
+            sync: SyncConfig::default(),
+            nodes: BTreeMap::new(),
+            down_since: BTreeMap::new(),
+            crashes,
+            restarts: Vec::new(),
+            recoveries: Vec::new(),
+        }
+    }
+
+    pub fn with_sync_config(mut self, sync: SyncConfig) -> Self {
+        self.sync = sync;
+        self
+    }
+
+    // everyone joins the network first, then they all start
+    pub async fn start(&mut self, ids: impl IntoIterator<Item = ValidatorId>) {
+        let mut transports = Vec::new();
+        for id in ids {
+            transports.push((id, self.sim.connect(id).await));
+        }
+
+        for (id, transport) in transports {
+            let consensus = (self.make_consensus)(id);
+            let task = self.spawn(id, transport, consensus.clone());
+            self.nodes.insert(id, Slot { consensus, task: Some(task) });
+        }
+    }
+
+    // reliable delivery underneath if it's on - a byzantine node still gets its messages acked and resent
+    fn spawn(&self, id: ValidatorId, transport: SimTransport, consensus: ConsensusHandle) -> JoinHandle<()> {
+        match &self.sim.config().reliable {
+            Some(config) => {
+                let transport = ReliableTransport::new(transport, id, Self::peers(&consensus), config.clone(), self.sim.random_u64());
+                self.spawn_on(id, transport, consensus)
+            }
+            None => self.spawn_on(id, transport, consensus),
+        }
+    }
+
+    // byzantine_nodes get the honest node on a transport that misbehaves for it
+    fn spawn_on<T: Transport + Sync>(&self, id: ValidatorId, transport: T, consensus: ConsensusHandle) -> JoinHandle<()> {
+        let byzantine = self.sim.config().byzantine_nodes.iter().find(|(node, _)| *node == id);
+        if let Some(&(_, behavior)) = byzantine {
+            let transport = ByzantineTransport::new(transport, id, behavior, consensus.clone());
+            let node = Node::with_consensus(id, transport, consensus).with_sync_config(self.sync.clone());
+            return tokio::spawn(node.run_node());
+        }
+
+        let node = Node::with_consensus(id, transport, consensus).with_sync_config(self.sync.clone());
+        tokio::spawn(node.run_node())
+    }
+
+    // every validator in order, for transports that do their own fan out
+    fn peers(consensus: &ConsensusHandle) -> Vec<ValidatorId> {
+        let mut peers: Vec<ValidatorId> = consensus.validator_set().validators.keys().copied().collect();
+        peers.sort();
+        peers
+    }
+
+    // the node's current state - a fresh restart swaps it out
+    pub fn consensus(&self, id: ValidatorId) -> Option<&ConsensusHandle> {
+        self.nodes.get(&id).map(|slot| &slot.consensus)
+    }
+
+    pub fn is_up(&self, id: ValidatorId) -> bool {
+        self.nodes.get(&id).is_some_and(|slot| slot.task.is_some())
+    }
+
+    pub async fn crash(&mut self, id: ValidatorId) {
+        let Some(task) = self.nodes.get_mut(&id).and_then(|slot| slot.task.take()) else {
+            return;
+        };
+        task.abort();
+        self.sim.disconnect(id).await;
+        self.down_since.insert(id, self.sim.elapsed());
+    }
+
+    pub async fn restart(&mut self, id: ValidatorId, restart: Restart) {
+        if self.is_up(id) || !self.nodes.contains_key(&id) {
+            return;
+        }
+
+        // what it has to catch up to
+        let (mut target_round, mut target_commits) = (0, 0);
+        for (_, slot) in self.nodes.iter().filter(|(_, slot)| slot.task.is_some()) {
+            target_round = target_round.max(slot.consensus.current_round().await);
+            target_commits = target_commits.max(slot.consensus.commit_count().await);
+        }
+
+        let consensus = match restart {
+            Restart::Fresh => (self.make_consensus)(id),
+            Restart::Persisted => self.nodes[&id].consensus.clone(),
+        };
+        let round_at_restart = consensus.current_round().await;
+        let commits_at_restart = consensus.commit_count().await;
+
+        let transport = self.sim.connect(id).await;
+        let task = self.spawn(id, transport, consensus.clone());
+        self.nodes.insert(id, Slot { consensus, task: Some(task) });
+
+        let now = self.sim.elapsed();
+        self.recoveries.push(Recovery {
+            node: id,
+            restart,
+            crashed_at: self.down_since.remove(&id).unwrap_or(now),
+            restarted_at: now,
+            round_at_restart,
+            commits_at_restart,
+            target_round,
+            target_commits,
+            round_caught_up: None,
+            commits_caught_up: None,
+        });
+    }
+
+    // play out the crash schedule and random churn until `until` since simulator start
+    pub async fn run(&mut self, until: Duration) {
+        let mut tick = interval(TICK);
+        while self.sim.elapsed() < until {
+            tick.tick().await;
+            let now = self.sim.elapsed();
+
+            let (due, later): (Vec<Crash>, Vec<Crash>) = self.crashes.drain(..).partition(|crash| crash.at <= now);
+            self.crashes = later;
+            for crash in due {
+                self.crash(crash.node).await;
+                if let Some(down_for) = crash.down_for {
+                    self.restarts.push((crash.node, now + down_for, crash.restart));
+                }
+            }
+
+            self.random_churn(now).await;
+
+            let (due, later): (Vec<_>, Vec<_>) = self.restarts.drain(..).partition(|(_, at, _)| *at <= now);
+            self.restarts = later;
+            for (id, _, restart) in due {
+                self.restart(id, restart).await;
+            }
+
+            self.track_recoveries(now).await;
+        }
+    }
+
+    async fn random_churn(&mut self, now: Duration) {
+        let config = self.sim.config();
+        let (rate, downtime, restart) = (config.node_churn, config.churn_downtime, config.churn_restart);
+        if rate <= 0.0 {
+            return;
+        }
+
+        // f minus the byzantine nodes - any more faults and nobody can form a quorum
+        let Some(vset) = self.nodes.values().next().map(|slot| slot.consensus.validator_set()) else {
+            return;
+        };
+        let faulty_limit = (vset.validators.len() - vset.threshold).saturating_sub(config.byzantine_nodes.len());
+
+        let ids: Vec<ValidatorId> = self.nodes.keys().copied().collect();
+        for id in ids {
+            if !self.is_up(id) || self.down_since.len() >= faulty_limit {
+                continue;
+            }
+            if self.sim.random() < rate * TICK.as_secs_f64() {
+                self.crash(id).await;
+                self.restarts.push((id, now + downtime, restart));
+            }
+        }
+    }
+
+    async fn track_recoveries(&mut self, now: Duration) {
+        for recovery in self.recoveries.iter_mut().filter(|recovery| !recovery.caught_up()) {
+            let Some(slot) = self.nodes.get(&recovery.node).filter(|slot| slot.task.is_some()) else {
+                continue;
+            };
+            if recovery.round_caught_up.is_none() && slot.consensus.current_round().await >= recovery.target_round {
+                recovery.round_caught_up = Some(now);
+            }
+            if recovery.commits_caught_up.is_none() && slot.consensus.commit_count().await >= recovery.target_commits {
+                recovery.commits_caught_up = Some(now);
+            }
+        }
+    }
+
+    pub fn report(&self) -> ChurnReport {
+        ChurnReport {
+            recoveries: self.recoveries.clone(),
+            still_down: self.down_since.keys().copied().collect(),
+        }
+    }
+}
+
+impl Drop for Cluster {
+    fn drop(&mut self) {
+        for task in self.nodes.values_mut().filter_map(|slot| slot.task.take()) {
+            task.abort();
+        }
+    }
+}
@@ -0,0 +1,323 @@
+use sha2::{Sha256, Digest};
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+
+use crate::{ValidatorId, ValidatorSet};
+
+/*
+    Common coin for Tusk leader election
+
+    Round robin means everyone (including an adversary) knows every future
+    leader. Instead each validator holds a share of a threshold key and puts
+    its coin share for wave w in its round 2w+2 block. Once f+1 shares are in
+    we can combine them into the coin and nobody could have known it before.
+
+    The scheme is a threshold "signature" over the wave number:
+        share_i = H(w)^(s_i)   where s_i = P(i) for a degree f polynomial P
+        coin    = H(w)^(P(0))  which we get from any f+1 shares via Lagrange
+    Shares come with a Chaum-Pedersen proof so bad shares can be thrown out.
+
+    NOTE: the group is toy-sized (62 bit) - this is a simulation not real crypto
+*/
+
+// safe prime p = 2q + 1, we work in the subgroup of squares which has prime order q
+pub(crate) const P: u64 = 4_611_686_018_427_377_339;
+pub(crate) const Q: u64 = 2_305_843_009_213_688_669;
+// 4 = 2^2 is a square so it generates the order q subgroup
+pub(crate) const G: u64 = 4;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CoinShare {
+    pub wave: u32,
+    pub signer: ValidatorId,
+    pub value: u64,
+    // Chaum-Pedersen proof that value uses the same secret as the signer's public key
+    pub challenge: u64,
+    pub response: u64,
+}
+
+// public side of the dealing - everyone gets the same copy
+#[derive(Debug, Clone)]
+pub struct CoinPublicKeys {
+    pub verification_keys: HashMap<ValidatorId, u64>,
+    pub threshold: usize,
+}
+
+// one validator's view: its secret share plus everyone's public keys
+#[derive(Debug, Clone)]
+pub struct ThresholdCoin {
+    pub id: ValidatorId,
+    secret: u64,
+    public: Arc<CoinPublicKeys>,
+}
+
+#[derive(Debug, Clone)]
+pub enum Coin {
+    // round robin leaders - predictable but handy for tests
+    Deterministic,
+    Threshold(ThresholdCoin),
+}
+
+pub(crate) fn mul_mod(a: u64, b: u64, m: u64) -> u64 {
+    ((a as u128 * b as u128) % m as u128) as u64
+}
+
+pub(crate) fn pow_mod(mut base: u64, mut exp: u64, m: u64) -> u64 {
+    let mut result = 1;
+    base %= m;
+    while exp > 0 {
+        if exp & 1 == 1 {
+            result = mul_mod(result, base, m);
+        }
+        base = mul_mod(base, base, m);
+        exp >>= 1;
+    }
+    result
+}
+
+// q is prime so a^(q-2) is the inverse
+fn inv_mod_q(a: u64) -> u64 {
+    pow_mod(a, Q - 2, Q)
+}
+
+pub(crate) fn hash_to_scalar(parts: &[&[u8]]) -> u64 {
+    let mut hasher = Sha256::new();
+    for part in parts {
+        hasher.update(part);
+    }
+    let digest: [u8; 32] = hasher.finalize().into();
+    u64::from_le_bytes(digest[..8].try_into().unwrap()) % Q
+}
+
+// map the wave onto the order q subgroup by squaring
+fn hash_to_group(wave: u32) -> u64 {
+    let mut hasher = Sha256::new();
+    hasher.update(b"tusk-coin");
+    hasher.update(wave.to_le_bytes());
+    let digest: [u8; 32] = hasher.finalize().into();
+    let x = u64::from_le_bytes(digest[..8].try_into().unwrap()) % P;
+    pow_mod(x.max(2), 2, P)
+}
+
+fn proof_challenge(verification_key: u64, base: u64, value: u64, commit_g: u64, commit_h: u64) -> u64 {
+    hash_to_scalar(&[
+        &G.to_le_bytes(),
+        &base.to_le_bytes(),
+        &verification_key.to_le_bytes(),
+        &value.to_le_bytes(),
+        &commit_g.to_le_bytes(),
+        &commit_h.to_le_bytes(),
+    ])
+}
+
+/*
+    Trusted dealer setup: pick a random degree f polynomial from the seed
+    and hand P(id) to each validator. Returns one ThresholdCoin per validator
+*/
+pub fn deal_coin(validator_set: &ValidatorSet, seed: u64) -> HashMap<ValidatorId, ThresholdCoin> {
+    let threshold = validator_set.validity_threshold();
+    let coefficients: Vec<u64> = (0..threshold as u64)
+        .map(|k| hash_to_scalar(&[b"coin-dealer", &seed.to_le_bytes(), &k.to_le_bytes()]))
+        .collect();
+
+    // horner's method mod q
+    let evaluate = |x: u64| coefficients.iter().rev()
+        .fold(0, |acc, c| (mul_mod(acc, x % Q, Q) + c) % Q);
+
+    let secrets: HashMap<ValidatorId, u64> = validator_set.validators.keys()
+        .map(|&id| (id, evaluate(id as u64)))
+        .collect();
+
+    let public = Arc::new(CoinPublicKeys {
+        verification_keys: secrets.iter().map(|(&id, &s)| (id, pow_mod(G, s, P))).collect(),
+        threshold,
+    });
+
+    secrets.into_iter()
+        .map(|(id, secret)| (id, ThresholdCoin { id, secret, public: Arc::clone(&public) }))
+        .collect()
+}
+
+impl ThresholdCoin {
+    pub fn share(&self, wave: u32) -> CoinShare {
+        let base = hash_to_group(wave);
+        let value = pow_mod(base, self.secret, P);
+
+        // deterministic nonce so we don't need an rng in here
+        let nonce = hash_to_scalar(&[b"coin-nonce", &self.secret.to_le_bytes(), &wave.to_le_bytes()]);
+        let commit_g = pow_mod(G, nonce, P);
+        let commit_h = pow_mod(base, nonce, P);
+        let verification_key = self.public.verification_keys[&self.id];
+        let challenge = proof_challenge(verification_key, base, value, commit_g, commit_h);
+        let response = (nonce + mul_mod(challenge, self.secret, Q)) % Q;
+
+        CoinShare { wave, signer: self.id, value, challenge, response }
+    }
+
+    pub fn verify_share(&self, share: &CoinShare) -> bool {
+        self.public.verify_share(share)
+    }
+
+    // combine f+1 valid shares for the wave - None until we have enough
+    pub fn combine(&self, wave: u32, shares: &[CoinShare]) -> Option<u64> {
+        self.public.combine(wave, shares)
+    }
+
+    pub fn public_keys(&self) -> &CoinPublicKeys {
+        &self.public
+    }
+}
+
+// checking and combining shares only needs the public side - a light client can do it too
+impl CoinPublicKeys {
+    pub fn verify_share(&self, share: &CoinShare) -> bool {
+        let Some(&verification_key) = self.verification_keys.get(&share.signer) else {
+            return false;
+        };
+        let base = hash_to_group(share.wave);
+
+        // recompute the commitments: g^z / vk^c and h^z / share^c
+        let undo = |b: u64, v: u64| mul_mod(
+            pow_mod(b, share.response, P),
+            pow_mod(v, Q - share.challenge % Q, P),
+            P,
+        );
+        let commit_g = undo(G, verification_key);
+        let commit_h = undo(base, share.value);
+
+        share.challenge == proof_challenge(verification_key, base, share.value, commit_g, commit_h)
+    }
+
+    // combine f+1 valid shares for the wave - None until we have enough
+    pub fn combine(&self, wave: u32, shares: &[CoinShare]) -> Option<u64> {
+        let mut seen = HashSet::new();
+        let mut picked: Vec<&CoinShare> = shares.iter()
+            .filter(|s| s.wave == wave && self.verify_share(s) && seen.insert(s.signer))
+            .collect();
+        if picked.len() < self.threshold {
+            return None;
+        }
+
+        // any f+1 shares give the same answer, sort so every node uses the same ones anyway
+        picked.sort_by_key(|s| s.signer);
+        picked.truncate(self.threshold);
+
+        let mut coin = 1;
+        for share in &picked {
+            let x_i = share.signer as u64 % Q;
+            // lagrange coefficient at 0
+            let mut lambda = 1;
+            for other in &picked {
+                let x_j = other.signer as u64 % Q;
+                if x_j != x_i {
+                    lambda = mul_mod(lambda, mul_mod(x_j, inv_mod_q((x_j + Q - x_i) % Q), Q), Q);
+                }
+            }
+            coin = mul_mod(coin, pow_mod(share.value, lambda, P), P);
+        }
+
+        Some(coin)
+    }
+}
+
+impl Coin {
+    // only the threshold coin has shares to hand out
+    pub fn share(&self, wave: u32) -> Option<CoinShare> {
+        match self {
+            Coin::Deterministic => None,
+            Coin::Threshold(coin) => Some(coin.share(wave)),
+        }
+    }
+
+    pub fn verify_share(&self, share: &CoinShare) -> bool {
+        match self {
+            Coin::Deterministic => true,
+            Coin::Threshold(coin) => coin.verify_share(share),
+        }
+    }
+
+    pub fn owner(&self) -> Option<ValidatorId> {
+        match self {
+            Coin::Deterministic => None,
+            Coin::Threshold(coin) => Some(coin.id),
+        }
+    }
+
+    /*
+        Slot value for the wave, or None if the coin for it isn't revealed yet.
+        The leader schedule maps it onto a validator - for the deterministic
+        coin it's just the leader round so we get round robin back
+     */
+    pub fn wave_value(&self, wave: u32, leader_round: u32, shares: &[CoinShare]) -> Option<u64> {
+        wave_value(self.public_keys(), wave, leader_round, shares)
+    }
+
+    pub fn public_keys(&self) -> Option<&CoinPublicKeys> {
+        match self {
+            Coin::Deterministic => None,
+            Coin::Threshold(coin) => Some(coin.public_keys()),
+        }
+    }
+}
+
+// Coin::wave_value from the public keys alone - None for the keys means the deterministic coin
+pub fn wave_value(keys: Option<&CoinPublicKeys>, wave: u32, leader_round: u32, shares: &[CoinShare]) -> Option<u64> {
+    match keys {
+        None => Some(leader_round as u64),
+        Some(keys) => keys.combine(wave, shares),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ValidatorInfo;
+
+    fn create_validators() -> ValidatorSet {
+        let validators = (1..=4).map(|id| ValidatorInfo { id, stake: 1 }).collect();
+        ValidatorSet::new(validators)
+    }
+
+    #[test]
+    fn test_any_quorum_gives_same_coin() {
+        let vset = create_validators();
+        let coins = deal_coin(&vset, 7);
+
+        let shares: Vec<CoinShare> = (1..=4).map(|id| coins[&id].share(3)).collect();
+        for share in &shares {
+            assert!(coins[&1].verify_share(share));
+        }
+
+        // f+1 = 2 shares, different pairs
+        let a = coins[&1].combine(3, &shares[0..2]).unwrap();
+        let b = coins[&2].combine(3, &shares[2..4]).unwrap();
+        let c = coins[&3].combine(3, &[shares[0], shares[3]]).unwrap();
+        assert_eq!(a, b);
+        assert_eq!(a, c);
+
+        // not enough shares yet
+        assert!(coins[&1].combine(3, &shares[0..1]).is_none());
+        // different wave different coin
+        let next: Vec<CoinShare> = (1..=2).map(|id| coins[&id].share(4)).collect();
+        assert_ne!(a, coins[&1].combine(4, &next).unwrap());
+    }
+
+    #[test]
+    fn test_bad_share_rejected() {
+        let vset = create_validators();
+        let coins = deal_coin(&vset, 7);
+
+        let mut forged = coins[&2].share(1);
+        forged.value = mul_mod(forged.value, G, P);
+        assert!(!coins[&1].verify_share(&forged));
+
+        // share claiming to be from someone else
+        let mut stolen = coins[&2].share(1);
+        stolen.signer = 3;
+        assert!(!coins[&1].verify_share(&stolen));
+
+        // forged share doesn't count towards the threshold
+        let honest = coins[&1].share(1);
+        assert!(coins[&1].combine(1, &[honest, forged]).is_none());
+    }
+}
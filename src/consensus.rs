@@ -3,33 +3,191 @@
 #![allow(unused_imports)] 
 
 use std::{collections::{HashMap, HashSet}, path::Ancestors, vec};
-use std::sync::Arc;
-use tokio::sync::RwLock;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use futures::stream::{self, BoxStream};
+use tokio::sync::{RwLock, watch};
 
-use crate::{Block, Certificate, Hash, Transaction, ValidatorId, ValidatorSet, dag, types};
+use crate::{Block, Certificate, CommittedSubDag, Hash, Transaction, ValidatorId, ValidatorSet, Vote, dag, types};
 use crate::{dag::DAG};
+use crate::coin::Coin;
+use crate::keys::SigningKey;
+use crate::ordering::{ConsensusMode, DagView, OrderingEngine};
+use crate::proof::CommitProof;
+use crate::synchronizer::RoundStatus;
+
+// how far back weak_parents looks for orphans - anything older than this is given up on,
+    // uncertified headers and our votes that old are dropped too
+pub const WEAK_LINK_DEPTH: u32 = 50;
 
 #[derive(Default)]
 pub struct ConsensusState {
+    // certified blocks only - everything the engine and proposals look at
     pub dag: DAG,
+    // headers still waiting on their certificate, they move into the dag once it's valid
+    pub pending_headers: HashMap<Hash, Block>,
     pub current_round: u32,
     pub committed_blocks: HashSet<Hash>,
     pub certificates: HashMap<Hash, Certificate>,
+    // sequence number for the next committed sub-dag
+    pub next_sequence: u64,
+    // every committed sub-dag, indexed by sequence number
+    pub commit_log: Vec<CommittedSubDag>,
+    // (voter, author, round) -> the one block that voter signed for that slot
+    pub votes_cast: HashMap<(ValidatorId, ValidatorId, u32), Hash>,
+    // parents of certified blocks that aren't in the dag yet - holes we have to fetch.
+        // maps to the lowest round of a block pointing at it
+    pub missing_parents: HashMap<Hash, u32>,
+}
+
+// why a voter refused to sign a header
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum VoteError {
+    UnknownBlock,
+    UnknownVoter(ValidatorId),
+    // we don't hold that validator's secret key so we can't sign for it
+    NoSigningKey(ValidatorId),
+    // already signed a different block from this author in this round
+    Equivocation { author: ValidatorId, round: u32 },
+    // non-genesis block without strong parents
+    NoParents { round: u32 },
+    // strong parents have to be from exactly round - 1, weak links from before that
+    BadParentRound { round: u32, parent_round: u32 },
+    // parents we don't have certified yet - worth retrying once they show up
+    MissingParents(Vec<Hash>),
+    // further back than WEAK_LINK_DEPTH - we no longer remember what we signed there
+    TooOld { round: u32 },
+}
+
+impl std::fmt::Display for VoteError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            VoteError::UnknownBlock => write!(f, "Not a valid block - not in dag"),
+            VoteError::UnknownVoter(_) => write!(f, "Not a valid voter - not in validator set"),
+            VoteError::NoSigningKey(voter) => write!(f, "No signing key for validator {}", voter),
+            VoteError::Equivocation { author, round } => write!(f, "Already voted for a different block from {} in round {}", author, round),
+            VoteError::NoParents { round } => write!(f, "Block in round {} has no parents", round),
+            VoteError::BadParentRound { round, parent_round } => write!(f, "Block in round {} has a parent from round {}", round, parent_round),
+            VoteError::MissingParents(missing) => write!(f, "Missing {} parents", missing.len()),
+            VoteError::TooOld { round } => write!(f, "Block in round {} is too old to vote on", round),
+        }
+    }
+}
+
+impl std::error::Error for VoteError {}
+
+// ordered commits for subscribers - see ConsensusHandle::subscribe
+pub type CommitStream = BoxStream<'static, CommittedSubDag>;
+
+impl ConsensusState {
+    // a header we know about, certified or not
+    pub fn header(&self, hash: &Hash) -> Option<&Block> {
+        self.pending_headers.get(hash).or_else(|| self.dag.get_block(hash))
+    }
+
+    // move a pending header into the dag once its certificate holds - true if it moved
+    fn promote(&mut self, hash: &Hash, validator_set: &ValidatorSet) -> Result<bool, String> {
+        let certified = self.certificates.get(hash)
+            .is_some_and(|cert| cert.is_valid_cert(validator_set));
+        if !certified {
+            return Ok(false);
+        }
+
+        // the certificate has to be for the round the header is actually in - a
+            // certificate that beat its header only gets this check now. Dropped so a good one can take its place
+        if let (Some(cert), Some(block)) = (self.certificates.get(hash), self.pending_headers.get(hash))
+            && cert.round != block.round {
+            let err = format!("Certificate for round {} but block is in round {}", cert.round, block.round);
+            self.certificates.remove(hash);
+            return Err(err);
+        }
+
+        match self.pending_headers.remove(hash) {
+            Some(block) => {
+                self.missing_parents.remove(hash);
+                for parent in block.parents.iter().chain(&block.weak_parents) {
+                    if !self.dag.contains_block(parent) {
+                        let lowest = self.missing_parents.entry(*parent).or_insert(block.round);
+                        *lowest = (*lowest).min(block.round);
+                    }
+                }
+                self.dag.insert_block(block)?;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    // what the ordering engine gets to look at
+    pub fn view<'a>(&'a self, validator_set: &'a ValidatorSet) -> DagView<'a> {
+        DagView {
+            dag: &self.dag,
+            certificates: &self.certificates,
+            committed: &self.committed_blocks,
+            validator_set,
+            current_round: self.current_round,
+            next_sequence: self.next_sequence,
+            last_digest: self.last_digest(),
+        }
+    }
+
+    // head of the hash chain - all zeros before the first commit
+    pub fn last_digest(&self) -> Hash {
+        self.commit_log.last().map_or([0; 32], |sub_dag| sub_dag.digest)
+    }
 }
 
 #[derive(Clone)]
 pub struct ConsensusHandle {
     state: Arc<RwLock<ConsensusState>>,
     validator_set: Arc<ValidatorSet>,
+    coin: Arc<Coin>,
+    // secret vote keys we sign with - just our own on a real node, tests play every validator
+    keys: Arc<HashMap<ValidatorId, SigningKey>>,
+    // what the engine was built from - commit proofs need its leader rules
+    mode: ConsensusMode,
+    // commit rule - std Mutex is fine since we never hold it across an await
+    engine: Arc<Mutex<Box<dyn OrderingEngine>>>,
+    // bumped to the commit log length after every commit so subscribers wake up
+    commits: Arc<watch::Sender<u64>>,
 }
 
 impl ConsensusHandle {
+    // deterministic (round robin) coin - fine for tests
     pub fn new(validator_set: ValidatorSet) -> Self {
+        Self::with_coin(validator_set, Coin::Deterministic)
+    }
+
+    pub fn with_coin(validator_set: ValidatorSet, coin: Coin) -> Self {
+        Self::with_mode(validator_set, coin, ConsensusMode::default())
+    }
+
+    pub fn with_mode(validator_set: ValidatorSet, coin: Coin, mode: ConsensusMode) -> Self {
+        let coin = Arc::new(coin);
         Self {
             state: Arc::new(RwLock::new(ConsensusState::default())),
+            engine: Arc::new(Mutex::new(mode.build(Arc::clone(&coin), &validator_set))),
             validator_set: Arc::new(validator_set),
+            coin,
+            keys: Arc::new(HashMap::new()),
+            mode,
+            commits: Arc::new(watch::Sender::new(0)),
         }
     }
+
+    // without keys the handle can still follow along but can't vote
+    pub fn with_keys(mut self, keys: impl IntoIterator<Item = SigningKey>) -> Self {
+        self.keys = Arc::new(keys.into_iter().map(|key| (key.id, key)).collect());
+        self
+    }
+
+    pub fn engine_name(&self) -> &'static str {
+        self.engine.lock().unwrap().name()
+    }
+
+    pub fn leader_timeout(&self) -> Duration {
+        self.engine.lock().unwrap().leader_timeout()
+    }
     /* Narwhal Methods:
         1. Block proposal
         2. Voting
@@ -38,58 +196,227 @@ impl ConsensusHandle {
 
     // Author is broadcasting block to other nodes
     pub async fn propose_block(&mut self, txs: Vec<Transaction>, author: ValidatorId) -> Result<Block, String> {
-        // annotate parents so that .collect() will give me a Vec
-        
         let env = self.state.read().await;
-        let parents: Vec<Hash> = env.dag.get_frontier().iter().copied().collect();
-
-        // do I want to maintain my block
+        let round = env.current_round;
+
+        // strong parents: certified blocks from the previous round
+            // round 0 is genesis so no parents
+        let parents = if round == 0 {
+            Vec::new()
+        } else {
+            self.strong_parents(&env, round - 1)?
+        };
+        let weak_parents = self.weak_parents(&env, round, &parents);
 
         // new block
-        let block = Block::new(txs, parents, author, env.current_round);
+        let mut block = Block::new(txs, parents, author, round).with_weak_parents(weak_parents);
         drop(env);
+
+        // even rounds from 2 on reveal the coin for the wave that started two rounds ago
+            // only our own blocks get our share
+        if round >= 2 && round % 2 == 0 && self.coin.owner() == Some(author)
+            && let Some(share) = self.coin.share(round / 2 - 1) {
+            block = block.with_coin_share(share);
+        }
         
-        // ?.. I guess I haven't voted yet
+        // not certified yet - waits with the other pending headers until the votes are in
         {
             let mut env = self.state.write().await;
-            env.dag.insert_block(block.clone())?;
+            env.pending_headers.insert(block.hash, block.clone());
         }
 
         Ok(block)        
     }
 
-    // Vote on if block is fine
-    pub async fn vote_block(&mut self, block_hash: &Hash, voter: ValidatorId) -> Result<(), String> {
+    // need certs from 2f+1 different authors in the previous round
+    fn strong_parents(&self, env: &ConsensusState, prev_round: u32) -> Result<Vec<Hash>, String> {
+        let (parents, authors) = self.certified_in_round(env, prev_round);
+
+        if authors < self.validator_set.threshold {
+            return Err(format!("Not enough certified parents in round {} - have {}", prev_round, authors));
+        }
+
+        Ok(parents)
+    }
+
+    // certified blocks of a round and how many distinct authors they come from
+    fn certified_in_round(&self, env: &ConsensusState, round: u32) -> (Vec<Hash>, usize) {
+        let view = env.view(&self.validator_set);
+        let mut blocks = Vec::new();
+        let mut authors = HashSet::new();
+
+        for hash in env.dag.get_round_blocks(round) {
+            if let (true, Some(block)) = (view.is_certified(&hash), env.dag.get_block(&hash)) {
+                authors.insert(block.author);
+                blocks.push(hash);
+            }
+        }
+
+        (blocks, authors.len())
+    }
+
+    /* 
+        orphans: certified blocks older than the previous round that
+        nothing we already point at reaches and that haven't been committed.
+        Linking them lets slow validators still get their txs ordered
+     */
+    fn weak_parents(&self, env: &ConsensusState, round: u32, parents: &[Hash]) -> Vec<Hash> {
+        if round < 2 {
+            return Vec::new();
+        }
+        // only look back a fixed number of rounds so the walk doesn't grow with the dag
+        let lowest = round.saturating_sub(WEAK_LINK_DEPTH);
+
+        // everything reachable from the strong parents is already covered
+        let mut covered = HashSet::new();
+        env.dag.extend_history_above(&mut covered, parents.iter().copied(), lowest);
+
+        // newest first so an orphan that links an older orphan covers it
+        let view = env.view(&self.validator_set);
+        let mut weak = Vec::new();
+        for old_round in (lowest..round - 1).rev() {
+            for hash in env.dag.get_round_blocks(old_round) {
+                if view.is_certified(&hash) && !covered.contains(&hash) && !env.committed_blocks.contains(&hash) {
+                    env.dag.extend_history_above(&mut covered, [hash], lowest);
+                    weak.push(hash);
+                }
+            }
+        }
+
+        weak
+    }
+
+    // Vote on if block is fine - records it straight into our local cert (the author's side, see add_vote)
+        // hands back the certificate if this vote completed it, same as add_vote
+    pub async fn vote_block(&mut self, block_hash: &Hash, voter: ValidatorId) -> Result<Option<Certificate>, String> {
+        let vote = self.create_vote(block_hash, voter).await.map_err(|err| err.to_string())?;
+        self.add_vote(vote).await
+    }
+
+    /*
+        Signed vote for a header - goes back to the block's author only.
+        Voter side safety rules, so a byzantine author can't get two
+        conflicting blocks certified:
+            - at most one block per (author, round) - asking again for the same block is fine
+            - strong parents all from round - 1 (none for genesis), weak links from further back
+            - every parent already certified in our dag
+     */
+    pub async fn create_vote(&self, block_hash: &Hash, voter: ValidatorId) -> Result<Vote, VoteError> {
+        let mut env = self.state.write().await;
         // check if valid block
-        {
-            let env = self.state.read().await;
-            if !env.dag.contains_block(block_hash) {
-                return Err("Not a valid block - not in dag".to_string());
+        let Some(block) = env.header(block_hash) else {
+            return Err(VoteError::UnknownBlock);
+        };
+
+        // check if valid voter
+        if !self.validator_set.validators.contains_key(&voter){
+            return Err(VoteError::UnknownVoter(voter));
+        }
+        let Some(key) = self.keys.get(&voter) else {
+            return Err(VoteError::NoSigningKey(voter));
+        };
+
+        if block.round < env.current_round.saturating_sub(WEAK_LINK_DEPTH) {
+            return Err(VoteError::TooOld { round: block.round });
+        }
+
+        let missing: Vec<Hash> = block.parents.iter().chain(&block.weak_parents)
+            .filter(|parent| !env.dag.contains_block(parent))
+            .copied()
+            .collect();
+        if !missing.is_empty() {
+            return Err(VoteError::MissingParents(missing));
+        }
+
+        if block.round > 0 && block.parents.is_empty() {
+            return Err(VoteError::NoParents { round: block.round });
+        }
+        for parent in &block.parents {
+            let parent_round = env.dag.get_block(parent).map_or(0, |p| p.round);
+            if parent_round + 1 != block.round {
+                return Err(VoteError::BadParentRound { round: block.round, parent_round });
+            }
+        }
+        for weak in &block.weak_parents {
+            let parent_round = env.dag.get_block(weak).map_or(0, |p| p.round);
+            if parent_round + 1 >= block.round {
+                return Err(VoteError::BadParentRound { round: block.round, parent_round });
             }
+        }
 
-            // check if valid voter
-            if !self.validator_set.validators.contains_key(&voter){
-                return Err("Not a valid voter - not in validator set".to_string());
+        let (author, round) = (block.author, block.round);
+        match env.votes_cast.get(&(voter, author, round)) {
+            Some(voted) if voted != block_hash => return Err(VoteError::Equivocation { author, round }),
+            _ => {
+                env.votes_cast.insert((voter, author, round), *block_hash);
             }
         }
-        // drop lock
+
+        Ok(Vote::new(*block_hash, round, key))
+    }
+
+    // a bare signature, none of the voter side checks - only for a byzantine node to misuse
+    pub fn sign_vote(&self, block_hash: &Hash, round: u32, voter: ValidatorId) -> Option<Vote> {
+        self.keys.get(&voter).map(|key| Vote::new(*block_hash, round, key))
+    }
+
+    /*
+        Author side: collect votes for our block. Hands back the certificate
+        exactly once, on the vote that gets it to 2f+1 - that's when the
+        author broadcasts it
+     */
+    pub async fn add_vote(&mut self, vote: Vote) -> Result<Option<Certificate>, String> {
+        if !self.validator_set.validators.contains_key(&vote.voter) {
+            return Err("Not a valid voter - not in validator set".to_string());
+        }
+        if !vote.verify(&self.validator_set) {
+            return Err(format!("Invalid vote signature from {}", vote.voter));
+        }
+
+        let mut env = self.state.write().await;
+        let Some(round) = env.header(&vote.block_hash).map(|block| block.round) else {
+            return Err("Not a valid block - not in dag".to_string());
+        };
+        if vote.round != round {
+            return Err(format!("Vote for round {} but block is in round {}", vote.round, round));
+        }
 
         // create cert if does not exist so we can vote on it
-        {
-            let mut env = self.state.write().await;
+        let cert = env
+            .certificates
+            .entry(vote.block_hash)
+            .or_insert_with(|| Certificate::new(vote.block_hash, round));
+
+        // same voter twice doesn't count twice
+        if cert.signatures.iter().any(|(voter, _)| *voter == vote.voter) {
+            return Ok(None);
+        }
+        cert.add_signature(vote.voter, vote.signature);
 
-            let round = env.current_round;
-            let cert = env
-                .certificates
-                .entry(*block_hash)
-                .or_insert_with(|| Certificate::new(*block_hash, round));
+        if cert.signatures.len() != self.validator_set.threshold {
+            return Ok(None);
+        }
+
+        let cert = cert.clone();
+        env.promote(&vote.block_hash, &self.validator_set)?;
+        Ok(Some(cert))
+    }
 
-            let sig = [voter as u8; 64];
-            cert.add_signature(voter, sig);
+    // certificate from another author - check every signature before we trust it
+    pub async fn accept_certificate(&mut self, cert: Certificate) -> Result<(), String> {
+        cert.verify(&self.validator_set)?;
 
+        let mut env = self.state.write().await;
+        if let Some(block) = env.header(&cert.block_hash)
+            && block.round != cert.round {
+            return Err(format!("Certificate for round {} but block is in round {}", cert.round, block.round));
         }
-        // drop lock
 
+        // we may have a partial one from our own vote - the full one replaces it
+            // if the header hasn't shown up yet it gets promoted when it does
+        let hash = cert.block_hash;
+        env.certificates.insert(hash, cert);
+        env.promote(&hash, &self.validator_set)?;
         Ok(())
     }
 
@@ -98,8 +425,25 @@ impl ConsensusHandle {
         if !self.validator_set.validators.contains_key(&block.author) {
             return Err("Block author not in set".to_string());
         }
+        if !block.verify() {
+            return Err("Block does not match its hash".to_string());
+        }
+        // same window commit_blocks keeps below us - otherwise anyone can fill pending_headers with far off rounds
+        if block.round > env.current_round + WEAK_LINK_DEPTH {
+            return Err(format!("Block from round {} is too far ahead of round {}", block.round, env.current_round));
+        }
+
+        if let Some(share) = &block.coin_share
+            && (share.signer != block.author || !self.coin.verify_share(share)) {
+            return Err("Invalid coin share".to_string());
+        }
 
-        env.dag.insert_block(block)?;
+        // certificate might have beaten the header here
+        let hash = block.hash;
+        if !env.dag.contains_block(&hash) {
+            env.pending_headers.insert(hash, block);
+            env.promote(&hash, &self.validator_set)?;
+        }
         Ok(())
     }
 
@@ -119,103 +463,103 @@ impl ConsensusHandle {
     }
     */
 
-    /*  Tusk Methods:
-        choose a leader = pick validator
-        leader proposes = block gets made (in this case already made)
-        consensus = which blocks are committed    
+    /*  Ordering Methods:
+        the engine (tusk, bullshark, round robin - see ordering.rs)
+        picks the leaders, we just keep track of what got committed
      */
 
-    #[allow(clippy::collapsible_if)]
-    pub async fn get_leader(&self, round:u32) -> Option<Hash> {
-        //println!("run get leader");
-        let leader = choose_leader(round, self.validator_set.validators.len() as u32);
-
-        // find the block that the leader proposed
-        let env = self.state.read().await;
-        if let Some(leader_hash) = env.dag.get_author_round_block(leader, round) {
-            
-            // if the leader has a cert
-            if let Some(leader_cert) = env.certificates.get(&leader_hash) {
-
-                // if the leader cert is valid
-                if leader_cert.is_valid_cert(&self.validator_set) {
-                    //println!("I have a leader");
-                    return Some(leader_hash)
-                }
-            }
+    // run the ordering engine over what we have and mark whatever it commits
+    pub async fn commit_blocks(&mut self) -> Vec<CommittedSubDag> {
+        let mut env = self.state.write().await;
+        let lowest = env.current_round.saturating_sub(WEAK_LINK_DEPTH);
+
+        let mut view = env.view(&self.validator_set);
+        // a leader's history with holes in it would commit a different sub-dag than everyone else's,
+            // so the engine only gets to see the rounds below the lowest block that has one. Holes never
+            // expire - 2f+1 voters had the parent certified, so fetching it from one of them always works eventually
+        if let Some(&lowest) = env.missing_parents.values().min() {
+            view.current_round = view.current_round.min(lowest);
         }
+        let committed = self.engine.lock().unwrap().try_commit(&view);
 
-        None
+        for sub_dag in &committed {
+            env.committed_blocks.extend(sub_dag.block_hashes());
+        }
+        env.next_sequence += committed.len() as u64;
+        env.commit_log.extend(committed.iter().cloned());
+        // headers still uncertified this far back can't be weak linked anymore, so they'd never get ordered.
+            // anything newer stays - late votes can still certify it, even below the last committed leader
+        env.pending_headers.retain(|_, block| block.round >= lowest);
+        // create_vote refuses anything that old, so what we signed there can go too
+        env.votes_cast.retain(|&(_, _, round), _| round >= lowest);
+        self.commits.send_replace(env.commit_log.len() as u64);
 
+        committed
     }
 
-    // if the blocks have enough votes then we can commit them
-        // where are these blocks located?
-        // they don't all have to be frontier
-    #[allow(clippy::collapsible_if)]
-    pub async fn commit_blocks(&mut self) ->Vec<Hash> {
-        let mut committed = Vec::new();
+    /*
+        Stream of committed sub-dags in order, starting at from_sequence
+        (0 replays everything). Any number of subscribers can be open.
+
+        Subscribers pull from the commit log at their own pace, so a slow
+        consumer just falls behind instead of blocking consensus or piling up
+        a buffer - nothing is read until the consumer asks for the next item.
+        The stream ends once every ConsensusHandle is gone and it has caught up
+     */
+    pub fn subscribe(&self, from_sequence: u64) -> CommitStream {
+        let state = Arc::clone(&self.state);
+        let commits = self.commits.subscribe();
+
+        Box::pin(stream::unfold((state, commits, from_sequence), |(state, mut commits, next)| async move {
+            loop {
+                let sub_dag = state.read().await.commit_log.get(next as usize).cloned();
+                if let Some(sub_dag) = sub_dag {
+                    return Some((sub_dag, (state, commits, next + 1)));
+                }
 
-        let tusk_round = {
-            let env = self.state.read().await;
-            if env.current_round < 2 {
-                return committed;
+                // nothing new yet - wait for the next commit
+                commits.changed().await.ok()?;
             }
+        }))
+    }
 
-            // we reduce round to keep it around 3 
-            env.current_round - 2
-        };
+    // running digest after the commit with this sequence - compare with another node to spot divergence
+    pub async fn commit_digest(&self, sequence: u64) -> Option<Hash> {
+        self.state.read().await.commit_log.get(sequence as usize).map(|sub_dag| sub_dag.digest)
+    }
 
-        if let Some(leader_block) = self.get_leader(tusk_round).await {
-            let mut env = self.state.write().await;
+    // everything a light client needs to check the tx is final - see proof.rs
+    pub async fn commit_proof(&self, tx_id: u64) -> Option<CommitProof> {
+        CommitProof::build(&*self.state.read().await, &self.validator_set, &self.mode, self.coin.public_keys(), tx_id)
+    }
 
-            if env.committed_blocks.insert(leader_block) {
-                committed.push(leader_block);
+    // leader timeouts (bullshark): is it ok to leave the current round yet
+    pub async fn leader_ready(&self) -> bool {
+        let env = self.state.read().await;
+        self.engine.lock().unwrap().leader_ready(&env.view(&self.validator_set))
+    }
 
-                // commit ancestors that have certs
-                for anc in env.dag.get_ancestors(&leader_block) {
-                    if env.certificates.contains_key(&anc) && env.committed_blocks.insert(anc) {
-                        committed.push(anc);
-                    }
-                }
-            }
-        }
+    // where the current round stands - the node's synchronizer decides when to move on from this
+    pub async fn round_status(&self) -> RoundStatus {
+        let env = self.state.read().await;
+        let (_, certified_authors) = self.certified_in_round(&env, env.current_round);
 
-        /*
-        // if round greater than 2
-        if self.current_round >= 2 {
-            let tusk_round = self.current_round - 2;
-
-            // if exists a leader
-            if let Some(leader_block) = self.get_leader(tusk_round) {
-                println!("have leader, go to commit blocks");
-
-                // if leader is not already committed (unlikely)
-                if !self.committed_blocks.contains(&leader_block) {
-                    println!("ah I found it");
-                    committed.push(leader_block);
-                    self.committed_blocks.insert(leader_block);
-
-                    // we commit all the causal ancestors as well
-                    let ancestors = self.dag.get_ancestors(&leader_block);
-                    println!("there are {} descendants", ancestors.len());
-                    for ancestor in ancestors {
-                        // if they have a cert
-                        if let Some(cert) = self.certificates.get(&ancestor) {
-
-                            // if the descendant isn't already in committed_blocks Hashset
-                            if self.committed_blocks.insert(ancestor) {
-                                committed.push(ancestor);
-                            }
-                        }
-                    }
-                    println!("commited blocks are {}", self.committed_blocks.len());
-                }
-            }
+        RoundStatus {
+            round: env.current_round,
+            certified_authors,
+            leader_ready: self.engine.lock().unwrap().leader_ready(&env.view(&self.validator_set)),
         }
-        */
+    }
 
-        committed
+    // who could lead `round` by the engine's schedule as it stands
+    pub async fn leaders(&self, round: u32) -> Vec<ValidatorId> {
+        let env = self.state.read().await;
+        let view = env.view(&self.validator_set);
+        self.engine.lock().unwrap().leaders(&view, round)
+    }
+
+    pub fn validator_set(&self) -> &ValidatorSet {
+        &self.validator_set
     }
 
     // since each handle has its own state - can't keep the rounds in the simulation
@@ -224,6 +568,68 @@ impl ConsensusHandle {
         env.current_round += 1;
     }
 
+    /*
+        Behind everyone else (just restarted, or cut off for a while): jump
+        straight to the newest round we already hold a quorum of certificates
+        for instead of walking up one round at a time. Returns the new round.
+        A quorum one round up is just everyone else being quicker - the
+        synchronizer takes us there once we've proposed and waited on the leader
+     */
+    pub async fn catch_up(&self) -> Option<u32> {
+        let mut env = self.state.write().await;
+        let highest = env.dag.highest_round()?;
+        let round = (env.current_round + 2..=highest).rev()
+            .find(|&round| self.certified_in_round(&env, round).1 >= self.validator_set.threshold)?;
+        env.current_round = round;
+        Some(round)
+    }
+
+    // parents of certified blocks we still don't have - leaders above them wait until these are fetched
+    pub async fn missing_parents(&self) -> Vec<Hash> {
+        let mut missing: Vec<Hash> = self.state.read().await.missing_parents.keys().copied().collect();
+        missing.sort();
+        missing
+    }
+
+    // parents of this (certified) block that aren't in our dag
+    pub async fn missing_parents_of(&self, hash: &Hash) -> Vec<Hash> {
+        let env = self.state.read().await;
+        let Some(block) = env.dag.get_block(hash) else {
+            return Vec::new();
+        };
+        block.parents.iter().chain(&block.weak_parents)
+            .filter(|parent| !env.dag.contains_block(parent))
+            .copied()
+            .collect()
+    }
+
+    pub async fn current_round(&self) -> u32 {
+        self.state.read().await.current_round
+    }
+
+    // length of the commit log
+    pub async fn commit_count(&self) -> u64 {
+        self.state.read().await.commit_log.len() as u64
+    }
+
+    // certified and in the dag
+    pub async fn dag_contains(&self, hash: &Hash) -> bool {
+        self.state.read().await.dag.contains_block(hash)
+    }
+
+    // a block from our dag with the certificate that got it there - what we hand out to peers missing it
+    pub async fn certified_block(&self, hash: &Hash) -> Option<(Block, Certificate)> {
+        let env = self.state.read().await;
+        let block = env.dag.get_block(hash)?.clone();
+        let cert = env.certificates.get(hash)?.clone();
+        Some((block, cert))
+    }
+
+    // still waiting on its certificate
+    pub async fn is_pending(&self, hash: &Hash) -> bool {
+        self.state.read().await.pending_headers.contains_key(hash)
+    }
+
     // separate check valid
     pub async fn cert_is_valid(&self, hash: &Hash) -> bool {
         let env = self.state.read().await;
@@ -242,7 +648,7 @@ impl ConsensusHandle {
 
 
 pub fn choose_leader(round:u32, validator_count: u32) -> u32 {
-    // skip using shared coin
-        // go round-robin
+    // round-robin - only used by the deterministic coin
+        // the real leader comes from the shared coin (coin.rs)
     1 + (round % validator_count)
 }
\ No newline at end of file
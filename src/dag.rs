@@ -19,6 +19,8 @@ pub struct DAG {
     //adj list
     children: HashMap<Hash, HashSet<Hash>>,
     parents: HashMap<Hash, HashSet<Hash>>,
+    // weak links are kept apart so paths/votes only follow strong edges
+    weak_parents: HashMap<Hash, HashSet<Hash>>,
 
     // quick lookup
     what_round: HashMap<u32, Vec<Hash>>,
@@ -35,6 +37,7 @@ impl Default for DAG {
             blocks: HashMap::new(),
             children: HashMap::new(),
             parents: HashMap::new(),
+            weak_parents: HashMap::new(),
             what_round: HashMap::new(),
             frontier: HashSet::new(),
             curr_round: 0,
@@ -43,6 +46,7 @@ impl Default for DAG {
 }
 
 impl DAG {
+    #[allow(clippy::unwrap_or_default)]
     pub fn insert_block(&mut self, block: Block) -> Result<(), String> {
         //let block_hash = block.hash;
         // since we clone we can still use it?
@@ -70,6 +74,15 @@ impl DAG {
             self.frontier.remove(parent_hash);
         }
 
+        // weak links also take the old block off the frontier
+        for weak_hash in &block.weak_parents {
+            self.weak_parents.entry(block.hash)
+                .or_default()
+                .insert(*weak_hash);
+
+            self.frontier.remove(weak_hash);
+        }
+
         // new block is the frontier
         self.frontier.insert(block.hash);
 
@@ -92,6 +105,7 @@ impl DAG {
 
     }
 
+    #[allow(clippy::collapsible_if)]
     pub fn get_author_round_block(&self, author: ValidatorId, round: u32) -> Option<Hash> {
         if let Some(round_blocks) = self.what_round.get(&round) {
             for block_hash in round_blocks {
@@ -115,10 +129,19 @@ impl DAG {
         &self.frontier
     }
 
+    pub fn get_round_blocks(&self, round: u32) -> Vec<Hash> {
+        self.what_round.get(&round).cloned().unwrap_or_default()
+    }
+
+    pub fn highest_round(&self) -> Option<u32> {
+        self.what_round.keys().max().copied()
+    }
+
 }
 
 
 #[test]
+#[allow(non_snake_case)]
 fn test_dag_methods() {
     let mut dummy_DAG = DAG::default();
     let dummy_block = Block::new(vec![], vec![], 1, 0);
@@ -230,6 +253,52 @@ impl DAG {
         }
         descendants
     }
+
+    pub fn get_weak_parents(&self, hash: &Hash) -> Vec<Hash> {
+        self.weak_parents.get(hash)
+            .map(|set| set.iter().copied().collect())
+            .unwrap_or_default()
+    }
+
+    // same DFS as get_ancestors but also follows weak links
+        // this is what gets committed with a leader so orphans get ordered too
+    pub fn get_causal_history(&self, hash: &Hash) -> HashSet<Hash> {
+        let mut history = HashSet::new();
+        let mut stack = vec![*hash];
+
+        while let Some(current) = stack.pop() {
+            let strong = self.parents.get(&current).into_iter().flatten();
+            let weak = self.weak_parents.get(&current).into_iter().flatten();
+
+            for parent in strong.chain(weak) {
+                if history.insert(*parent) {
+                    stack.push(*parent);
+                }
+            }
+        }
+        history
+    }
+
+    /*
+        get_causal_history for a whole set of blocks in one walk, added onto
+        `history`. Doesn't go below lowest_round, and doesn't walk past
+        anything already in `history` - so only call it on a set built by it
+    */
+    pub fn extend_history_above(&self, history: &mut HashSet<Hash>, from: impl IntoIterator<Item = Hash>, lowest_round: u32) {
+        let mut stack: Vec<Hash> = from.into_iter().filter(|hash| history.insert(*hash)).collect();
+
+        while let Some(current) = stack.pop() {
+            let strong = self.parents.get(&current).into_iter().flatten();
+            let weak = self.weak_parents.get(&current).into_iter().flatten();
+
+            for parent in strong.chain(weak) {
+                let above = self.get_block(parent).is_none_or(|block| block.round >= lowest_round);
+                if above && history.insert(*parent) {
+                    stack.push(*parent);
+                }
+            }
+        }
+    }
 }
 
 #[test]
@@ -271,6 +340,33 @@ fn test_traversal() {
 
 }
 
+#[test]
+fn test_weak_links() {
+    let mut dummy_dag = DAG::default();
+    let block_a = Block::new(vec![], vec![], 1, 0);
+    let hash_a = block_a.hash;
+    dummy_dag.insert_block(block_a).unwrap();
+
+    // slow block from round 0 that nobody in round 1 picked up
+    let orphan = Block::new(vec![], vec![], 2, 0);
+    let hash_orphan = orphan.hash;
+    dummy_dag.insert_block(orphan).unwrap();
+
+    let block_b = Block::new(vec![], vec![hash_a], 1, 1);
+    let hash_b = block_b.hash;
+    dummy_dag.insert_block(block_b).unwrap();
+
+    let block_c = Block::new(vec![], vec![hash_b], 1, 2).with_weak_parents(vec![hash_orphan]);
+    let hash_c = block_c.hash;
+    dummy_dag.insert_block(block_c).unwrap();
+
+    // strong traversal doesn't see the orphan but causal history does
+    assert!(!dummy_dag.get_ancestors(&hash_c).contains(&hash_orphan));
+    assert!(dummy_dag.get_causal_history(&hash_c).contains(&hash_orphan));
+    assert_eq!(dummy_dag.get_weak_parents(&hash_c), vec![hash_orphan]);
+    assert!(!dummy_dag.get_frontier().contains(&hash_orphan));
+}
+
 // TODO:
     // topological sort
     // path finding - lets say BFS
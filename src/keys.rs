@@ -0,0 +1,94 @@
+use std::collections::HashMap;
+
+use crate::coin::{G, P, Q, hash_to_scalar, mul_mod, pow_mod};
+use crate::{Hash, Signature, ValidatorId, ValidatorSet};
+
+/*
+    Vote signatures: Schnorr over the same toy group the coin uses.
+    Each validator holds a secret x and the validator set carries its
+    public key g^x, so a vote only checks out if the voter's secret
+    signed it - knowing the voter and the block isn't enough anymore.
+
+    signature = (c, s) where c = H(pk, g^k, block) and s = k + c*x
+*/
+
+#[derive(Debug, Clone)]
+pub struct SigningKey {
+    pub id: ValidatorId,
+    secret: u64,
+}
+
+fn challenge(public_key: u64, commit: u64, block_hash: &Hash) -> u64 {
+    hash_to_scalar(&[b"narwhal-vote", &public_key.to_le_bytes(), &commit.to_le_bytes(), block_hash])
+}
+
+/*
+    Trusted dealer setup like deal_coin: derive every validator's secret from
+    the seed, record the public keys in the set and hand back the secrets
+*/
+pub fn deal_keys(validator_set: &mut ValidatorSet, seed: u64) -> HashMap<ValidatorId, SigningKey> {
+    let keys: HashMap<ValidatorId, SigningKey> = validator_set.validators.keys()
+        .map(|&id| {
+            // zero would make g^x = 1 for every block
+            let secret = hash_to_scalar(&[b"key-dealer", &seed.to_le_bytes(), &id.to_le_bytes()]).max(1);
+            (id, SigningKey { id, secret })
+        })
+        .collect();
+
+    validator_set.public_keys = keys.iter().map(|(&id, key)| (id, key.public_key())).collect();
+    keys
+}
+
+impl SigningKey {
+    pub fn public_key(&self) -> u64 {
+        pow_mod(G, self.secret, P)
+    }
+
+    pub fn sign(&self, block_hash: &Hash) -> Signature {
+        // deterministic nonce so we don't need an rng in here
+        let nonce = hash_to_scalar(&[b"vote-nonce", &self.secret.to_le_bytes(), block_hash]);
+        let c = challenge(self.public_key(), pow_mod(G, nonce, P), block_hash);
+        let s = (nonce + mul_mod(c, self.secret, Q)) % Q;
+
+        let mut signature = [0u8; 16];
+        signature[..8].copy_from_slice(&c.to_le_bytes());
+        signature[8..].copy_from_slice(&s.to_le_bytes());
+        signature
+    }
+}
+
+// only needs the public key from the validator set
+pub fn verify_signature(public_key: u64, block_hash: &Hash, signature: &Signature) -> bool {
+    let c = u64::from_le_bytes(signature[..8].try_into().unwrap());
+    let s = u64::from_le_bytes(signature[8..].try_into().unwrap());
+    if c >= Q || s >= Q {
+        return false;
+    }
+
+    // g^s / pk^c gives back g^k for an honest signature
+    let commit = mul_mod(pow_mod(G, s, P), pow_mod(public_key, Q - c, P), P);
+    c == challenge(public_key, commit, block_hash)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ValidatorInfo;
+
+    #[test]
+    fn test_only_the_secret_signs() {
+        let mut vset = ValidatorSet::new((1..=4).map(|id| ValidatorInfo { id, stake: 1 }).collect());
+        let keys = deal_keys(&mut vset, 3);
+        let block = [7u8; 32];
+
+        let signature = keys[&2].sign(&block);
+        assert!(verify_signature(vset.public_keys[&2], &block, &signature));
+        // someone else's key or another block doesn't check out
+        assert!(!verify_signature(vset.public_keys[&3], &block, &signature));
+        assert!(!verify_signature(vset.public_keys[&2], &[8u8; 32], &signature));
+
+        // a different dealing gives different keys
+        let other = deal_keys(&mut vset.clone(), 4);
+        assert!(!verify_signature(vset.public_keys[&2], &block, &other[&2].sign(&block)));
+    }
+}
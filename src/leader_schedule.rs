@@ -0,0 +1,152 @@
+use std::collections::HashMap;
+
+use crate::{CommittedSubDag, ValidatorId, ValidatorSet};
+
+/*
+    Reputation based leader schedule
+
+    Plain round robin keeps handing leader slots to validators that crashed
+    or are too slow to get certified, and every one of those wastes a slot.
+    Instead we score validators by how many of their blocks made it into the
+    last K commits and drop the worst ones (at most f) from the rotation.
+
+    Scores only come from committed sub-dags, which every honest node outputs
+    in the same order, so everyone switches to the same schedule at the same
+    commit without sending anything extra
+*/
+
+#[derive(Debug, Clone)]
+pub struct ReputationConfig {
+    // recompute the schedule every K commits - 0 means plain round robin forever
+    pub commits_per_update: u64,
+    // how many low scorers to drop, capped at f
+    pub bad_leaders: usize,
+}
+
+impl Default for ReputationConfig {
+    fn default() -> Self {
+        Self {
+            commits_per_update: 10,
+            bad_leaders: 1,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct LeaderSchedule {
+    config: ReputationConfig,
+    validators: Vec<ValidatorId>,
+    max_bad: usize,
+    // who is in the rotation right now, sorted by id
+    schedule: Vec<ValidatorId>,
+    // blocks per author over the current window
+    scores: HashMap<ValidatorId, u64>,
+    commits_in_window: u64,
+}
+
+impl LeaderSchedule {
+    pub fn new(validator_set: &ValidatorSet, config: ReputationConfig) -> Self {
+        let mut validators: Vec<ValidatorId> = validator_set.validators.keys().copied().collect();
+        validators.sort();
+
+        Self {
+            config,
+            schedule: validators.clone(),
+            validators,
+            max_bad: validator_set.max_faulty(),
+            scores: HashMap::new(),
+            commits_in_window: 0,
+        }
+    }
+
+    // slot is a round (round robin) or a coin value - before any update this is choose_leader
+    pub fn leader(&self, slot: u64) -> ValidatorId {
+        self.schedule[(slot % self.schedule.len() as u64) as usize]
+    }
+
+    pub fn schedule(&self) -> &[ValidatorId] {
+        &self.schedule
+    }
+
+    // count the commit, returns true if the schedule changed
+    pub fn on_commit(&mut self, sub_dag: &CommittedSubDag) -> bool {
+        if self.config.commits_per_update == 0 {
+            return false;
+        }
+
+        for block in &sub_dag.blocks {
+            *self.scores.entry(block.author).or_default() += 1;
+        }
+        self.commits_in_window += 1;
+        if self.commits_in_window < self.config.commits_per_update {
+            return false;
+        }
+
+        // best first, ties broken by id so every node sorts the same way
+        let mut ranked: Vec<(u64, ValidatorId)> = self.validators.iter()
+            .map(|id| (self.scores.get(id).copied().unwrap_or(0), *id))
+            .collect();
+        ranked.sort_by(|a, b| b.0.cmp(&a.0).then(a.1.cmp(&b.1)));
+
+        // only drop validators that actually did worse than the best one
+        let best = ranked.first().map_or(0, |(score, _)| *score);
+        let drop = self.config.bad_leaders.min(self.max_bad);
+        let mut schedule: Vec<ValidatorId> = ranked.iter().enumerate()
+            .filter(|(i, (score, _))| *i < ranked.len() - drop || *score == best)
+            .map(|(_, (_, id))| *id)
+            .collect();
+        schedule.sort();
+
+        self.scores.clear();
+        self.commits_in_window = 0;
+
+        let changed = schedule != self.schedule;
+        self.schedule = schedule;
+        changed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Block, ValidatorInfo, choose_leader};
+
+    fn create_validators() -> ValidatorSet {
+        let validators = (1..=4).map(|id| ValidatorInfo { id, stake: 1 }).collect();
+        ValidatorSet::new(validators)
+    }
+
+    // sub-dag with one block from each author
+    fn sub_dag(sequence: u64, authors: &[ValidatorId]) -> CommittedSubDag {
+        let blocks: Vec<Block> = authors.iter().map(|&a| Block::new(vec![], vec![], a, sequence as u32)).collect();
+        CommittedSubDag::new(sequence, &blocks[0].clone(), blocks)
+    }
+
+    #[test]
+    fn test_starts_as_round_robin() {
+        let schedule = LeaderSchedule::new(&create_validators(), ReputationConfig::default());
+        for round in 0..12 {
+            assert_eq!(schedule.leader(round), choose_leader(round as u32, 4));
+        }
+    }
+
+    #[test]
+    fn test_drops_silent_validator() {
+        let config = ReputationConfig { commits_per_update: 3, bad_leaders: 1 };
+        let mut schedule = LeaderSchedule::new(&create_validators(), config);
+
+        // validator 4 never gets anything committed
+        assert!(!schedule.on_commit(&sub_dag(0, &[1, 2, 3])));
+        assert!(!schedule.on_commit(&sub_dag(1, &[1, 2, 3])));
+        assert!(schedule.on_commit(&sub_dag(2, &[1, 2, 3])));
+
+        assert_eq!(schedule.schedule(), &[1, 2, 3]);
+        assert!((0..12).all(|slot| schedule.leader(slot) != 4));
+
+        // everyone equal again - 4 is back in
+        for seq in 3..6 {
+            schedule.on_commit(&sub_dag(seq, &[1, 2, 3, 4]));
+        }
+        assert_eq!(schedule.schedule(), &[1, 2, 3, 4]);
+    }
+}
@@ -2,8 +2,24 @@ pub mod types;
 pub mod dag;
 //pub mod validator;
 pub mod consensus;
+pub mod coin;
+pub mod keys;
+pub mod leader_schedule;
+pub mod ordering;
+pub mod tusk;
+pub mod bullshark;
+pub mod proof;
+pub mod synchronizer;
+pub mod transport;
 pub mod network;
+pub mod topology;
+pub mod trace;
+pub mod codec;
+pub mod tcp;
 pub mod node;
+pub mod churn;
+pub mod byzantine;
+pub mod reliable;
 
 pub use types::*;
 pub use consensus::*;
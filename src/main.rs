@@ -1,45 +1,206 @@
-use narwhal_tusk::{network::*, node::*, types::*};
+use futures::StreamExt;
+use narwhal_tusk::{churn::*, coin::*, consensus::*, keys::deal_keys, network::*, node::*, ordering::ConsensusMode, reliable::*, tcp::*, trace::*, transport::{Transport, scripted}, types::*};
 
-#[tokio::main]
-async fn main() {
+fn main() {
     println!("run test");
 
-    let n: u32 = 10;
-    // simulation time in seconds
-    let time = 5;
-    let config = SimulationConfig{
+    // cargo run -- <tusk|bullshark|bullshark-fallback|round-robin> to swap the ordering engine
+    let mode: ConsensusMode = match std::env::args().nth(1) {
+        Some(name) => name.parse().unwrap_or_else(|err| panic!("{}", err)),
+        None => ConsensusMode::default(),
+    };
+
+    // cargo run -- <engine> tcp <id> <n> [base_port] runs one validator of a real cluster instead
+    let args: Vec<String> = std::env::args().collect();
+    if args.get(2).map(String::as_str) == Some("tcp") {
+        let arg = |i: usize, default: Option<u32>| args.get(i)
+            .map(|v| v.parse().unwrap_or_else(|_| panic!("bad number {}", v)))
+            .or(default)
+            .expect("usage: <engine> tcp <id> <n> [base_port]");
+        let runtime = tokio::runtime::Runtime::new().expect("can't start tokio");
+        runtime.block_on(run_tcp_validator(mode, arg(3, None), arg(4, None), arg(5, Some(9000)) as u16));
+        return;
+    }
+
+    // cargo run -- <engine> replay <trace file> <id> [n] plays a recorded run back to one node
+    if args.get(2).map(String::as_str) == Some("replay") {
+        let usage = "usage: <engine> replay <trace file> <id> [n]";
+        let trace = Trace::read(args.get(3).expect(usage)).unwrap_or_else(|err| panic!("{}", err));
+        let number = |i: usize| args.get(i).map(|v| v.parse().unwrap_or_else(|_| panic!("bad number {}", v)));
+        let (id, n) = (number(4).expect(usage), number(5).unwrap_or(10));
+        let runtime = SimulationConfig { virtual_time: true, ..Default::default() }.runtime().expect("can't start tokio");
+        runtime.block_on(replay_validator(mode, trace, id, n));
+        return;
+    }
+
+    let mut config = SimulationConfig{
+        mode: mode.clone(),
+        // protocol time flies by, a run takes as long as the cpu needs
+        virtual_time: true,
         ..Default::default()
         /*
-            or define
+            or define, e.g. split the network for a couple of seconds
+            faults: vec![NetworkFault::partition(vec![vec![1, 2, 3], vec![4, 5, 6, 7, 8, 9, 10]],
+                Duration::from_secs(1), Some(Duration::from_secs(3)))],
+            or crash a node and bring it back with nothing
+            crashes: vec![Crash { node: 3, at: Duration::from_secs(1), down_for: Some(Duration::from_secs(2)), restart: Restart::Fresh }],
+            or have a few nodes misbehave (names in byzantine.rs)
+            byzantine_nodes: vec![(2, "equivocate".parse().unwrap()), (7, "withhold-votes".parse().unwrap())],
+            or put the nodes in five AWS-like regions (more in topology.rs)
+            topology: Some(Topology::aws_spread(1..=10)),
+            or give every node a 10 Mbit/s link and node 1 a slow upload
+            bandwidth: Some(Bandwidth::symmetric(1_250_000)),
+            node_bandwidth: vec![(1, Bandwidth { upload: 125_000, download: 1_250_000 })],
+            or ack and resend everything over the lossy links
+            reliable: Some(ReliableConfig::default()),
          */
     };
+    // SEED=<n> cargo run replays a run exactly
+    if let Ok(seed) = std::env::var("SEED") {
+        config.seed = seed.parse().unwrap_or_else(|_| panic!("bad seed {}", seed));
+    }
+    // TRACE=<file> cargo run writes every message down to replay later (cargo run -- <engine> replay <file> <id>)
+    let trace = std::env::var("TRACE").ok();
+
+    let runtime = config.runtime().expect("can't start tokio");
+    runtime.block_on(simulate(config, trace));
+}
+
+async fn simulate(config: SimulationConfig, trace: Option<String>) {
+    let n: u32 = 10;
+    // simulated time in seconds
+    let time = 10;
 
     let vals= (1..=n)
         .map(|id| ValidatorInfo {id, stake: 1})
         .collect();
-    let vset = ValidatorSet::new(vals);
+    let mut vset = ValidatorSet::new(vals);
+    // trusted dealer hands out the coin key shares and vote keys - a fresh restart gets its keys back, keys live on disk
+    let coins = deal_coin(&vset, config.seed);
+    let keys = deal_keys(&mut vset, config.seed);
 
-    let mut sim = Simulator::new(config);
-    let net = sim.handle();
+    println!("Seed {}", config.seed);
+    let mode = config.mode.clone();
+    let sim = match trace {
+        Some(path) => match Simulator::new(config).with_trace(&path) {
+            Ok(sim) => sim,
+            Err(err) => {
+                println!("Can't write trace {}: {}", path, err);
+                return;
+            }
+        },
+        None => Simulator::new(config),
+    };
     let start = std::time::Instant::now();
 
-    let mut tasks = Vec::with_capacity(n as usize);
+    println!("Starting {} node {} simulation for {} seconds...", n, mode, time);
+    let make_consensus: MakeConsensus = std::sync::Arc::new(move |id| {
+        let coin = Coin::Threshold(coins[&id].clone());
+        ConsensusHandle::with_mode(vset.clone(), coin, mode.clone()).with_keys([keys[&id].clone()])
+    });
 
-    for id in 1..=n {
-        let rx = sim.register_node(id).await;
-        let node = Node::new(id, rx, vset.clone());
-        let net_clone = net.clone();
-        tasks.push(tokio::spawn( async move {
-            node.run_node(net_clone).await;
-        }));
-    }
+    // the cluster registers everyone before the first round 0 header goes out
+    let mut cluster = Cluster::new(sim.clone(), make_consensus);
+    cluster.start(1..=n).await;
 
-    println!("Starting {} node simulation for {} seconds...", n, time);
-    tokio::time::sleep(std::time::Duration::from_secs(time)).await;
-    println!("Shut down");
+    // follow node 1's ledger like an application/indexer would
+    let indexer = tokio::spawn(index_commits(cluster.consensus(1).unwrap().subscribe(0)));
+
+    // crashes and restarts play out while we wait
+    cluster.run(std::time::Duration::from_secs(time)).await;
+    println!("Shut down after {:?} simulated, {:?} real", sim.elapsed(), start.elapsed());
+    print!("{}", cluster.report());
+    if let Err(err) = sim.flush_trace() {
+        println!("Can't write trace: {}", err);
+    }
 
     // kill leftovers
-    for t in tasks {
-        t.abort();
+    indexer.abort();
+    drop(cluster);
+}
+
+/*
+    One validator per process, validator i listens on 127.0.0.1:base_port+i.
+    Start n of these (any order - peers get redialed until they're up).
+    Every process deals the coin and vote keys from the same fixed seed so
+    they line up - fine for a local demo, a real deployment needs a proper key ceremony
+*/
+async fn run_tcp_validator(mode: ConsensusMode, id: ValidatorId, n: u32, base_port: u16) {
+    let vals = (1..=n)
+        .map(|id| ValidatorInfo {id, stake: 1})
+        .collect();
+    let mut vset = ValidatorSet::new(vals);
+    let coin = Coin::Threshold(deal_coin(&vset, 42).remove(&id).expect("id not in the validator set"));
+    let key = deal_keys(&mut vset, 42).remove(&id).expect("id not in the validator set");
+
+    let addr = |i: ValidatorId| std::net::SocketAddr::from(([127, 0, 0, 1], base_port + i as u16));
+    let peers = (1..=n).map(|i| (i, addr(i))).collect();
+    let net = TcpNetwork::bind(id, addr(id), TcpConfig::default()).await
+        .unwrap_or_else(|err| panic!("can't listen on {}: {}", addr(id), err));
+    let transport = net.start(peers);
+
+    let consensus = ConsensusHandle::with_mode(vset, coin, mode.clone()).with_keys([key]);
+    let indexer = tokio::spawn(index_commits(consensus.subscribe(0)));
+
+    println!("Validator {}/{} running {} on {}", id, n, mode, addr(id));
+    // RELIABLE=1 acks and resends everything - a peer that's down for a while still gets it when it's back
+    let node_run = async {
+        if std::env::var("RELIABLE").is_ok_and(|v| v == "1") {
+            let transport = ReliableTransport::new(transport, id, (1..=n).collect(), ReliableConfig::default(), rand::random());
+            run(Node::with_consensus(id, transport, consensus)).await
+        } else {
+            run(Node::with_consensus(id, transport, consensus)).await
+        }
+    };
+    tokio::select! {
+        _ = node_run => {}
+        _ = tokio::signal::ctrl_c() => println!("Shutting down"),
+    }
+    indexer.abort();
+}
+
+/*
+    One node of a recorded simulation on its own, fed what it got in the
+    run at the times it got it - see trace.rs. n has to match the run, the
+    coin and keys come from the seed in the trace
+*/
+async fn replay_validator(mode: ConsensusMode, trace: Trace, id: ValidatorId, n: u32) {
+    let vals = (1..=n)
+        .map(|id| ValidatorInfo {id, stake: 1})
+        .collect();
+    let mut vset = ValidatorSet::new(vals);
+    let coin = Coin::Threshold(deal_coin(&vset, trace.seed).remove(&id).expect("id not in the validator set"));
+    let key = deal_keys(&mut vset, trace.seed).remove(&id).expect("id not in the validator set");
+    let consensus = ConsensusHandle::with_mode(vset, coin, mode).with_keys([key]);
+    let indexer = tokio::spawn(index_commits(consensus.subscribe(0)));
+
+    let (transport, mut script) = scripted((1..=n).collect());
+    let node = tokio::spawn(run(Node::with_consensus(id, transport, consensus)));
+
+    println!("Replaying {} messages to node {} (seed {})", trace.delivered_to(id).count(), id, trace.seed);
+    replay(&trace, id, &script).await;
+    // one more commit tick
+    tokio::time::sleep(std::time::Duration::from_millis(500)).await;
+    println!("Node {} sent {} messages, {} in the recording", id, script.drain_sent().len(), trace.sent_by(id).count());
+
+    node.abort();
+    indexer.abort();
+}
+
+// same loop whatever the node runs on
+async fn run<T: Transport>(node: Node<T>) {
+    node.run_node().await;
+}
+
+// print every commit node 1 makes
+async fn index_commits(mut commits: CommitStream) {
+    let mut total_txs = 0;
+    while let Some(sub_dag) = commits.next().await {
+        total_txs += sub_dag.transactions.len();
+        let leader = sub_dag.blocks.iter().find(|block| block.hash == sub_dag.leader).map_or(0, |block| block.author);
+        println!(
+            "commit #{} leader round {} (node {}) - {} blocks, {} txs ({} total)",
+            sub_dag.sequence, sub_dag.round, leader, sub_dag.blocks.len(), sub_dag.transactions.len(), total_txs
+        );
     }
 }
@@ -1,11 +1,19 @@
 
-use std::{collections::HashMap, sync::Arc, time::Duration};
+use std::{collections::{BTreeMap, HashMap}, io, path::Path, sync::{Arc, Mutex, OnceLock}, time::Duration};
 
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use tokio::runtime::{Builder, Runtime};
 use tokio::sync::{mpsc, RwLock};
-use tokio::time::sleep;
+use tokio::time::{sleep_until, Instant};
 
-use crate::{Block, Hash, ValidatorId, Certificate};
-use rand::Rng;
+use crate::byzantine::Behavior;
+use crate::ordering::ConsensusMode;
+use crate::reliable::ReliableConfig;
+use crate::topology::Topology;
+use crate::trace::{TraceKind, TraceWriter};
+use crate::transport::Transport;
+use crate::{Block, Certificate, Hash, ValidatorId, Vote};
 
 #[derive(Clone, Debug)]
 pub struct NetworkMsg {
@@ -14,46 +22,283 @@ pub struct NetworkMsg {
     pub payload: MessagePayload,
 }
 
+impl NetworkMsg {
+    // what it takes on the wire (codec::encode_msg) - what the bandwidth model charges for
+    pub fn size_bytes(&self) -> usize {
+        4 + 4 + self.payload.size_bytes() // from, to
+    }
+}
+
 #[derive(Clone, Debug)]
 pub enum MessagePayload {
     Block(Block),
-    Vote { block_hash: Hash, voter: ValidatorId},
-    // only need to send the cert
-    Certificate { block_hash: Hash}
+    // only goes to the block's author
+    Vote(Vote),
+    // the author's full certificate, receivers check the signatures
+    Certificate(Certificate),
+    // parents we can't vote without - answered with the block and its certificate
+    FetchCertificates(Vec<Hash>),
+    // reliable.rs: a payload numbered by its sender, acked by the receiver
+    Sequenced { session: u64, seq: u64, oldest: u64, payload: Box<MessagePayload> },
+    Ack { session: u64, seq: u64 },
+}
+
+impl MessagePayload {
+    pub fn size_bytes(&self) -> usize {
+        1 + match self { // tag
+            MessagePayload::Block(block) => block.size_bytes(),
+            MessagePayload::Vote(vote) => vote.size_bytes(),
+            MessagePayload::Certificate(cert) => cert.size_bytes(),
+            MessagePayload::FetchCertificates(missing) => 4 + missing.len() * 32,
+            MessagePayload::Sequenced { payload, .. } => 8 * 3 + payload.size_bytes(), // session, seq, oldest
+            MessagePayload::Ack { .. } => 8 * 2,
+        }
+    }
 }
 
 #[derive(Clone)]
 pub struct SimulationConfig {
+    // ordering engine every node runs - see ordering.rs
+    pub mode: ConsensusMode,
+    // every random choice the simulator makes (loss, latency, churn) comes from this
+    pub seed: u64,
+    // run on a paused clock that jumps straight to the next timer - see runtime()
+    pub virtual_time: bool,
     pub latency_ms: (u64,u64),
+    // per link latencies, latency_ms for whatever it leaves out - see topology.rs
+    pub topology: Option<Topology>,
     pub packet_loss_rate: f64,
+    // every node's link, None for unlimited - see Bandwidth
+    pub bandwidth: Option<Bandwidth>,
+    // nodes on a different link than the rest
+    pub node_bandwidth: Vec<(ValidatorId, Bandwidth)>,
+    // chance per node per second of a random crash - see churn.rs
     pub node_churn: f64,
-    pub byzantine_nodes: Vec<ValidatorId>,
+    // how long a randomly crashed node stays down and what it comes back with
+    pub churn_downtime: Duration,
+    pub churn_restart: Restart,
+    // crashes at fixed times on top of the random ones
+    pub crashes: Vec<Crash>,
+    // nodes that misbehave and how - see byzantine.rs
+    pub byzantine_nodes: Vec<(ValidatorId, Behavior)>,
+    // acks and retransmission between nodes, None sends everything once - see reliable.rs
+    pub reliable: Option<ReliableConfig>,
+    // partitions and dead links over the course of the run
+    pub faults: Vec<NetworkFault>,
 }
 
 impl  Default for SimulationConfig {
     fn default() -> Self {
         Self {
+            mode: ConsensusMode::default(),
+            seed: rand::random(),
+            virtual_time: false,
             latency_ms: (30,200),
+            topology: None,
             packet_loss_rate: 0.02,
+            bandwidth: None,
+            node_bandwidth: vec![],
             node_churn: 0.001,
+            churn_downtime: Duration::from_secs(1),
+            churn_restart: Restart::Persisted,
+            crashes: vec![],
             byzantine_nodes: vec![],
+            reliable: None,
+            faults: vec![],
+        }
+    }
+}
+
+// what a crashed node has when it comes back
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Restart {
+    // empty state, everything has to come from the peers
+    Fresh,
+    // everything it had when it went down, as if it was on disk
+    Persisted,
+}
+
+// take `node` down at `at` (from simulator start) and bring it back `down_for` later - None stays down
+#[derive(Clone, Debug)]
+pub struct Crash {
+    pub node: ValidatorId,
+    pub at: Duration,
+    pub down_for: Option<Duration>,
+    pub restart: Restart,
+}
+
+impl SimulationConfig {
+    pub fn latency_between(&self, from: ValidatorId, to: ValidatorId) -> (u64, u64) {
+        self.topology.as_ref()
+            .and_then(|topology| topology.latency_ms(from, to))
+            .unwrap_or(self.latency_ms)
+    }
+
+    pub fn bandwidth_of(&self, id: ValidatorId) -> Option<Bandwidth> {
+        self.node_bandwidth.iter()
+            .find(|(node, _)| *node == id)
+            .map(|(_, bandwidth)| *bandwidth)
+            .or(self.bandwidth)
+    }
+
+    /*
+        Runtime to run the simulation on. One thread, so what runs when only
+        depends on what the tasks do - with the same seed a run plays out
+        the same way every time. With virtual_time the clock is paused and
+        whenever every task is waiting on a timer it jumps to the next one,
+        so minutes of protocol time go by as fast as the cpu can do the work.
+        Tests get the same thing from #[tokio::test(start_paused = true)]
+     */
+    pub fn runtime(&self) -> std::io::Result<Runtime> {
+        Builder::new_current_thread()
+            .enable_all()
+            .start_paused(self.virtual_time)
+            .build()
+    }
+}
+
+/*
+    Bandwidth
+
+    Without it a message takes latency_ms whatever its size. With it, a
+    message of size_bytes first has to get out of the sender's upload link,
+    then crosses the network (latency_ms), then has to get through the
+    receiver's download link. Each link sends one message at a time in the
+    order they reach it, so a node broadcasting a big block holds up
+    everything it sends after, and a node everyone sends to at once backs
+    up the same way. Lost messages still used up the sender's upload
+*/
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Bandwidth {
+    // bytes per second
+    pub upload: u64,
+    pub download: u64,
+}
+
+impl Bandwidth {
+    pub fn symmetric(bytes_per_sec: u64) -> Self {
+        Self { upload: bytes_per_sec, download: bytes_per_sec }
+    }
+
+    // how long `bytes` keeps a link of `rate` bytes per second busy
+    fn transmit(bytes: usize, rate: u64) -> Duration {
+        Duration::from_secs_f64(bytes as f64 / rate.max(1) as f64)
+    }
+}
+
+/*
+    Scheduled network faults
+
+    Times are measured from when the Simulator was created. A fault cuts
+    messages that are sent while it's active and heals by itself at `end`
+    (None keeps it up for the whole run). Messages already in flight when
+    a fault starts still arrive
+*/
+#[derive(Clone, Debug)]
+pub struct NetworkFault {
+    pub kind: FaultKind,
+    pub start: Duration,
+    pub end: Option<Duration>,
+}
+
+#[derive(Clone, Debug)]
+pub enum FaultKind {
+    // nobody talks across groups - nodes that aren't in any group are left alone
+    Partition(Vec<Vec<ValidatorId>>),
+    // from -> to is dropped, to -> from still works
+    LinkDown { from: ValidatorId, to: ValidatorId },
+}
+
+impl NetworkFault {
+    pub fn partition(groups: Vec<Vec<ValidatorId>>, start: Duration, end: Option<Duration>) -> Self {
+        Self { kind: FaultKind::Partition(groups), start, end }
+    }
+
+    pub fn link_down(from: ValidatorId, to: ValidatorId, start: Duration, end: Option<Duration>) -> Self {
+        Self { kind: FaultKind::LinkDown { from, to }, start, end }
+    }
+
+    pub fn is_active(&self, at: Duration) -> bool {
+        at >= self.start && self.end.is_none_or(|end| at < end)
+    }
+
+    // does this fault drop a message from -> to sent at `at`
+    pub fn cuts(&self, from: ValidatorId, to: ValidatorId, at: Duration) -> bool {
+        if !self.is_active(at) {
+            return false;
+        }
+        match &self.kind {
+            FaultKind::Partition(groups) => {
+                let group_of = |id| groups.iter().position(|group| group.contains(&id));
+                match (group_of(from), group_of(to)) {
+                    (Some(a), Some(b)) => a != b,
+                    _ => false,
+                }
+            }
+            FaultKind::LinkDown { from: cut_from, to: cut_to } => from == *cut_from && to == *cut_to,
         }
     }
 }
 
 struct NetInner {
     config: SimulationConfig,
-    // TODO:routes will change over time
-    routes: RwLock<HashMap<ValidatorId, mpsc::UnboundedSender<NetworkMsg>>>
+    // fault schedule is relative to this
+    started: Instant,
+    rng: Mutex<StdRng>,
+    // when each node's upload and download link is free again - see Bandwidth
+    uploads: Mutex<HashMap<ValidatorId, Instant>>,
+    downloads: Mutex<HashMap<ValidatorId, Instant>>,
+    // ordered so a broadcast goes out (and draws its latencies) in the same order every run
+    routes: RwLock<BTreeMap<ValidatorId, mpsc::UnboundedSender<NetworkMsg>>>,
+    // set once by Simulator::with_trace
+    trace: OnceLock<TraceWriter>,
 }
 
 impl NetInner {
     fn new(config: SimulationConfig) -> Self {
         Self {
+            trace: OnceLock::new(),
+            rng: Mutex::new(StdRng::seed_from_u64(config.seed)),
             config,
-            routes: RwLock::new(HashMap::new()),
+            started: Instant::now(),
+            uploads: Mutex::new(HashMap::new()),
+            downloads: Mutex::new(HashMap::new()),
+            routes: RwLock::new(BTreeMap::new()),
+        }
+    }
+
+    fn record(&self, kind: TraceKind, msg: &NetworkMsg) {
+        if let Some(trace) = self.trace.get() {
+            trace.record(self.started.elapsed(), kind, msg);
         }
     }
+
+    // hand it over - the receiver can have crashed while it was on its way
+    fn deliver(&self, tx: &mpsc::UnboundedSender<NetworkMsg>, msg: NetworkMsg) {
+        if self.trace.get().is_none() {
+            let _ = tx.send(msg);
+            return;
+        }
+        let kind = if tx.send(msg.clone()).is_ok() { TraceKind::Deliver } else { TraceKind::Unreachable };
+        self.record(kind, &msg);
+    }
+
+    fn link_up(&self, from: ValidatorId, to: ValidatorId) -> bool {
+        let now = self.started.elapsed();
+        !self.config.faults.iter().any(|fault| fault.cuts(from, to, now))
+    }
+
+    // queue `bytes` on a node's link from `at` on - when it's all through
+    fn transmit(queue: &Mutex<HashMap<ValidatorId, Instant>>, id: ValidatorId, at: Instant, bytes: usize, rate: Option<u64>) -> Instant {
+        let Some(rate) = rate else {
+            return at;
+        };
+        let mut queue = queue.lock().unwrap();
+        let free = queue.entry(id).or_insert(at);
+        let done = (*free).max(at) + Bandwidth::transmit(bytes, rate);
+        *free = done;
+        done
+    }
 }
 
 #[derive(Clone)]
@@ -66,11 +311,22 @@ pub struct NetworkHandle {
     inner:Arc<NetInner>,
 }
 
+// a node's end of the simulator - see transport.rs
+pub struct SimTransport {
+    net: NetworkHandle,
+    rx: mpsc::UnboundedReceiver<NetworkMsg>,
+}
+
 impl Simulator {
     pub fn new(config: SimulationConfig) -> Self {
         Self { inner: Arc::new(NetInner::new(config)) }
     }
 
+    // time since the simulator was created - the clock the fault schedule runs on
+    pub fn elapsed(&self) -> Duration {
+        self.inner.started.elapsed()
+    }
+
     // create a handle at the start
     pub fn handle(&self) -> NetworkHandle {
         NetworkHandle {inner: Arc::clone(&self.inner)}
@@ -81,32 +337,95 @@ impl Simulator {
         self.inner.routes.write().await.insert(id,tx);
         rx
     }
+
+    // crashed node - whatever is still in flight to it is lost
+    pub async fn disconnect(&self, id: ValidatorId) {
+        self.inner.routes.write().await.remove(&id);
+    }
+
+    pub fn config(&self) -> &SimulationConfig {
+        &self.inner.config
+    }
+
+    // write every send, delivery and drop from here on to `path` - see trace.rs
+    pub fn with_trace(self, path: impl AsRef<Path>) -> io::Result<Self> {
+        let trace = TraceWriter::create(path, self.inner.config.seed)?;
+        if self.inner.trace.set(trace).is_err() {
+            return Err(io::Error::other("Simulator is already tracing"));
+        }
+        Ok(self)
+    }
+
+    // push what's been traced so far out to the file
+    pub fn flush_trace(&self) -> io::Result<()> {
+        match self.inner.trace.get() {
+            Some(trace) => trace.flush(),
+            None => Ok(()),
+        }
+    }
+
+    // uniform in [0, 1) from the seeded generator - anything random in a simulation draws from here
+    pub fn random(&self) -> f64 {
+        self.inner.rng.lock().unwrap().random()
+    }
+
+    pub fn random_u64(&self) -> u64 {
+        self.inner.rng.lock().unwrap().random()
+    }
+
+    // register and hand back everything the node needs to talk
+    pub async fn connect(&self, id: ValidatorId) -> SimTransport {
+        SimTransport { rx: self.register_node(id).await, net: self.handle() }
+    }
 }
 
 impl NetworkHandle {
-    pub async fn send(&self, mut msg: NetworkMsg) {
-        // packet loss
-        let loss = self.inner.config.packet_loss_rate.clamp(0.0, 1.0);
-        if rand::random::<f64>() < loss {
+    pub async fn send(&self, msg: NetworkMsg) {
+        let inner = &self.inner;
+        inner.record(TraceKind::Send, &msg);
+
+        // partitioned or the link is down
+        if !inner.link_up(msg.from, msg.to) {
+            inner.record(TraceKind::LinkDown, &msg);
             return
         }
 
-        let (low, high) = self.inner.config.latency_ms;
-        let latency = rand::rng().random_range(low..high);
+        let size = msg.size_bytes();
+        let upload = inner.config.bandwidth_of(msg.from).map(|bandwidth| bandwidth.upload);
+        let sent = NetInner::transmit(&inner.uploads, msg.from, Instant::now(), size, upload);
+
+        // packet loss and latency, from the seeded generator
+        let loss = inner.config.packet_loss_rate.clamp(0.0, 1.0);
+        let (low, high) = inner.config.latency_between(msg.from, msg.to);
+        let (lost, latency) = {
+            let mut rng = inner.rng.lock().unwrap();
+            // a matrix entry can be a fixed (n, n)
+            (rng.random::<f64>() < loss, rng.random_range(low..high.max(low + 1)))
+        };
+        if lost {
+            inner.record(TraceKind::Lost, &msg);
+            return
+        }
 
         // Option<Sender>
         let tx_opt = {
-            let routes = self.inner.routes.read().await;
+            let routes = inner.routes.read().await;
             routes.get(&msg.to).cloned()
         };
+        let Some(tx) = tx_opt else {
+            inner.record(TraceKind::Unreachable, &msg);
+            return
+        };
 
-        // delay with latency
-        if let Some(tx) = tx_opt {
-            tokio::spawn(async move {
-                sleep(Duration::from_millis(latency)).await;
-                let _ = tx.send(msg);
-            });
-        }
+        // out of the sender, across the network, then queued on the receiver's download
+        let inner = Arc::clone(inner);
+        let download = inner.config.bandwidth_of(msg.to).map(|bandwidth| bandwidth.download);
+        tokio::spawn(async move {
+            sleep_until(sent + Duration::from_millis(latency)).await;
+            let received = NetInner::transmit(&inner.downloads, msg.to, Instant::now(), size, download);
+            sleep_until(received).await;
+            inner.deliver(&tx, msg);
+        });
     }
 
     pub async fn broadcast(&self, from: ValidatorId, payload: MessagePayload) {
@@ -121,4 +440,40 @@ impl NetworkHandle {
             self.send(msg).await;
         }
     }
-}
\ No newline at end of file
+}
+
+impl Transport for SimTransport {
+    async fn send(&self, msg: NetworkMsg) {
+        self.net.send(msg).await;
+    }
+
+    async fn broadcast(&self, from: ValidatorId, payload: MessagePayload) {
+        self.net.broadcast(from, payload).await;
+    }
+
+    async fn recv(&mut self) -> Option<NetworkMsg> {
+        self.rx.recv().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fault_schedule() {
+        let ms = Duration::from_millis;
+        let split = NetworkFault::partition(vec![vec![1, 2], vec![3, 4, 5]], ms(1000), Some(ms(3000)));
+
+        assert!(!split.cuts(1, 3, ms(999)));
+        assert!(split.cuts(1, 3, ms(1000)) && split.cuts(4, 2, ms(2000)));
+        // same side, or not in the partition at all
+        assert!(!split.cuts(1, 2, ms(2000)) && !split.cuts(1, 6, ms(2000)));
+        // healed
+        assert!(!split.cuts(1, 3, ms(3000)));
+
+        let one_way = NetworkFault::link_down(1, 2, Duration::ZERO, None);
+        assert!(one_way.cuts(1, 2, ms(60_000)));
+        assert!(!one_way.cuts(2, 1, ms(60_000)));
+    }
+}
@@ -1,83 +1,265 @@
-use std::sync::Arc;
-use tokio::sync::{RwLock,mpsc};
-use tokio::time::{interval, Duration};
+use std::collections::HashMap;
 
-use crate::{ConsensusHandle, Transaction, ValidatorId, ValidatorSet, network::{MessagePayload, NetworkHandle, NetworkMsg}};
+use tokio::time::{interval, Duration, Instant};
 
-pub struct Node {
+use crate::coin::Coin;
+use crate::ordering::ConsensusMode;
+use crate::synchronizer::{RoundSynchronizer, SyncConfig};
+use crate::transport::Transport;
+use crate::{Block, ConsensusHandle, Hash, Transaction, ValidatorId, ValidatorSet, VoteError, network::{MessagePayload, NetworkMsg}};
+
+// a validator on whatever network it's given - simulator, tcp or a test script
+pub struct Node<T: Transport> {
     pub id: ValidatorId,
-    pub rx: mpsc::UnboundedReceiver<NetworkMsg>,
+    pub transport: T,
     pub consensus: ConsensusHandle,
+    pub sync: SyncConfig,
+    // when we last asked a peer for a missing block - see fetch
+    requested: HashMap<(Hash, ValidatorId), Instant>,
 }
 
-impl Node {
-    pub fn new(id:ValidatorId, rx: mpsc::UnboundedReceiver<NetworkMsg>, val_set: ValidatorSet) -> Self {
-        Self { 
-            id, 
-            rx, 
-            consensus: ConsensusHandle::new(val_set)
+// don't ask for the same block again sooner than this
+const FETCH_RETRY: Duration = Duration::from_millis(500);
+
+impl<T: Transport> Node<T> {
+    // deterministic coin - fine for tests
+    pub fn new(id:ValidatorId, transport: T, val_set: ValidatorSet, mode: ConsensusMode) -> Self {
+        Self::with_coin(id, transport, val_set, Coin::Deterministic, mode)
+    }
+
+    pub fn with_coin(id:ValidatorId, transport: T, val_set: ValidatorSet, coin: Coin, mode: ConsensusMode) -> Self {
+        Self {
+            id,
+            transport,
+            consensus: ConsensusHandle::with_mode(val_set, coin, mode),
+            sync: SyncConfig::default(),
+            requested: HashMap::new(),
         }
     }
 
-    pub async fn local_propose(&mut self, txs: Vec<Transaction>, net: &NetworkHandle) -> Result<(), String> {
+    pub fn with_consensus(id:ValidatorId, transport: T, consensus: ConsensusHandle) -> Self {
+        Self { id, transport, consensus, sync: SyncConfig::default(), requested: HashMap::new() }
+    }
+
+    pub async fn local_propose(&mut self, txs: Vec<Transaction>) -> Result<(), String> {
         let block = self.consensus.propose_block(txs, self.id).await?;
-        net.broadcast(self.id, MessagePayload::Block(block)).await;
+        self.transport.broadcast(self.id, MessagePayload::Block(block)).await;
         Ok(())
     }
 
-    pub async fn run_node(mut self, net: NetworkHandle) {
+    pub fn with_sync_config(mut self, sync: SyncConfig) -> Self {
+        self.sync = sync;
+        self
+    }
+
+    pub async fn run_node(mut self) {
 
-        let mut propose_tick = interval(Duration::from_millis(100));
         let mut commit_tick = interval(Duration::from_millis(200));
-        // let round be equal to 350 so that propose and commit are inside
-        let mut round_tick = interval(Duration::from_millis(350));
+        // rounds move on quorums not ticks - this just re-checks the timeouts
+        let mut sync_tick = interval(Duration::from_millis(50));
+
+        let vset = self.consensus.validator_set().clone();
+        let mut sync = RoundSynchronizer::new(
+            self.sync.clone(),
+            vset.threshold,
+            vset.validators.len(),
+            // bullshark: how long we're willing to sit in a round waiting on the leader
+            self.consensus.leader_timeout(),
+            Instant::now(),
+        );
+        // headers we'd vote for once their parents are certified here too
+        let mut waiting_on_parents: Vec<(Hash, ValidatorId)> = Vec::new();
+        // our header for the round we're in, until it's certified
+        let mut own_header: Option<Block> = None;
+        // who we can ask for missing history, and who we asked last
+        let mut peers: Vec<ValidatorId> = vset.validators.keys().copied().filter(|&id| id != self.id).collect();
+        peers.sort();
+        let mut fetch_from = 0;
 
         loop {
+            // biased so a run only depends on its seed - the ticks go first, they're rarely ready
+                // and a busy inbox would otherwise starve them
             tokio::select! {
+                biased;
+
+                _ = commit_tick.tick() => {
+                    // ask again in case the fetch or its answer got lost
+                    self.retry_votes(&mut waiting_on_parents, true).await;
+                    // holes in the dag hold up commits - ask someone new each time
+                    let missing = self.consensus.missing_parents().await;
+                    if !missing.is_empty() && !peers.is_empty() {
+                        fetch_from = (fetch_from + 1) % peers.len();
+                        self.fetch(missing, peers[fetch_from]).await;
+                    }
+                    self.requested.retain(|_, at| at.elapsed() < FETCH_RETRY);
+                    let _ = self.consensus.commit_blocks().await;
+                }
+
+                _ = sync_tick.tick() => {}
+
                 // if I receive a message then run the following
-                Some(msg) = self.rx.recv() => {
+                msg = self.transport.recv() => {
+                    // transport shut down (tcp stopped, script dropped) - nothing more will come in
+                    let Some(msg) = msg else {
+                        break;
+                    };
                     match msg.payload {
-                        // received block
+                        // received block: vote goes back to the author only
                         MessagePayload::Block(block) => {
-                            let hash = block.hash;
-                            let _ = self.consensus.accept_block(block).await;
-
-                            let _ = self.consensus.vote_block(&hash, self.id).await;
-                            net.broadcast(self.id, MessagePayload::Vote { block_hash: hash, voter: self.id }).await;
+                            let (hash, author) = (block.hash, block.author);
+                            if self.consensus.accept_block(block).await.is_ok() {
+                                if self.try_vote(hash, author, true).await {
+                                    waiting_on_parents.push((hash, author));
+                                }
+                                self.fetch_history(hash, msg.from).await;
+                            }
                         }
-                        // received vote
-                        MessagePayload::Vote { block_hash, voter } => {
-                            let _ = self.consensus.vote_block(&block_hash, voter).await;
-
-                            if self.consensus.cert_is_valid(&block_hash).await {
-                                net.broadcast(self.id, MessagePayload::Certificate { block_hash }).await;
+                        // received vote for one of our blocks: broadcast the cert once we hit 2f+1
+                        MessagePayload::Vote(vote) => {
+                            if let Ok(Some(cert)) = self.consensus.add_vote(vote).await {
+                                self.transport.broadcast(self.id, MessagePayload::Certificate(cert)).await;
+                                self.retry_votes(&mut waiting_on_parents, false).await;
+                                let _ = self.consensus.commit_blocks().await;
                             }
                         }
-                        // received cert: commit
-                        MessagePayload::Certificate { block_hash: _ } => {
-                            let _ = self.consensus.commit_blocks().await;
+                        // received cert: check it, then commit
+                        MessagePayload::Certificate(cert) => {
+                            let hash = cert.block_hash;
+                            if self.consensus.accept_certificate(cert).await.is_ok() {
+                                self.fetch_history(hash, msg.from).await;
+                                self.retry_votes(&mut waiting_on_parents, false).await;
+                                let _ = self.consensus.commit_blocks().await;
+                            }
                         }
+                        // someone is missing parents of one of our blocks - we have them certified
+                        MessagePayload::FetchCertificates(missing) => {
+                            for hash in missing {
+                                if let Some((block, cert)) = self.consensus.certified_block(&hash).await {
+                                    self.transport.send(NetworkMsg { from: self.id, to: msg.from, payload: MessagePayload::Block(block) }).await;
+                                    self.transport.send(NetworkMsg { from: self.id, to: msg.from, payload: MessagePayload::Certificate(cert) }).await;
+                                }
+                            }
+                        }
+                        // reliable.rs unwraps these - without it on our side there's nothing to ack with
+                        MessagePayload::Sequenced { .. } | MessagePayload::Ack { .. } => {}
                     }
                 }
+            }
 
-                // otherwise tick and then propose
-                _ = propose_tick.tick() => {
-                    let txs = vec![Transaction::new(format!("node {} tx", self.id))];
-                    if let Ok(block) = self.consensus.propose_block(txs, self.id).await {
-                        net.broadcast(self.id, MessagePayload::Block(block)).await;
-                    }
-                }
+            self.step_round(&mut sync, &mut own_header).await;
+        }
+    }
 
-                _ = commit_tick.tick() => {
-                    let _ = self.consensus.commit_blocks().await;
-                }
+    /*
+        vote for a header if the safety rules let us - true if it's only
+        waiting on parents. The author has all of them certified so that's
+        who we ask for the ones we missed
+     */
+    async fn try_vote(&mut self, hash: Hash, author: ValidatorId, fetch: bool) -> bool {
+        // already certified (e.g. a fetch answer) - nothing to sign
+        if self.consensus.dag_contains(&hash).await {
+            return false;
+        }
 
-                _ = round_tick.tick() => {
-                    self.consensus.advance_round().await;
+        match self.consensus.create_vote(&hash, self.id).await {
+            Ok(vote) => {
+                self.transport.send(NetworkMsg { from: self.id, to: author, payload: MessagePayload::Vote(vote) }).await;
+                false
+            }
+            Err(VoteError::MissingParents(missing)) => {
+                if fetch {
+                    self.fetch(missing, author).await;
                 }
+                true
+            }
+            // anything else is refused for good
+            Err(_) => false,
+        }
+    }
+
+    /*
+        a certified block whose parents we don't have: whoever sent it has
+        them, so ask right away. The answers can have holes of their own and
+        come back through here, so this walks back as far as we're missing
+     */
+    async fn fetch_history(&mut self, hash: Hash, from: ValidatorId) {
+        let missing = self.consensus.missing_parents_of(&hash).await;
+        self.fetch(missing, from).await;
+    }
+
+    /*
+        ask a peer for blocks we're missing. Walking back through history a
+        parent gets asked for once per child, and every answer has parents of
+        its own - without skipping what's already on its way that snowballs.
+        Only skipped per peer though, a slow (or lying) peer mustn't stop us
+        asking someone else
+     */
+    async fn fetch(&mut self, hashes: Vec<Hash>, to: ValidatorId) {
+        let now = Instant::now();
+        let wanted: Vec<Hash> = hashes.into_iter()
+            .filter(|hash| self.requested.get(&(*hash, to)).is_none_or(|at| now.duration_since(*at) >= FETCH_RETRY))
+            .collect();
+        if wanted.is_empty() || to == self.id {
+            return;
+        }
+
+        for hash in &wanted {
+            self.requested.insert((*hash, to), now);
+        }
+        self.transport.send(NetworkMsg { from: self.id, to, payload: MessagePayload::FetchCertificates(wanted) }).await;
+    }
 
+    async fn retry_votes(&mut self, waiting: &mut Vec<(Hash, ValidatorId)>, fetch: bool) {
+        let mut still_waiting = Vec::new();
+        for (hash, author) in waiting.drain(..) {
+            if self.try_vote(hash, author, fetch).await {
+                still_waiting.push((hash, author));
+            }
+        }
+        *waiting = still_waiting;
+    }
+
+    /*
+        propose once for the round we're in, then move to the next round as
+        soon as the synchronizer is happy - can run through several rounds
+        in one go if we were behind and the certificates are already here.
+        A header that's taking too long to certify gets sent again
+     */
+    async fn step_round(&mut self, sync: &mut RoundSynchronizer, own_header: &mut Option<Block>) {
+        // more than a round behind (restarted or cut off) - skip the rounds everyone else already finished
+        self.consensus.catch_up().await;
 
+        loop {
+            let status = self.consensus.round_status().await;
+
+            if sync.needs_proposal(status.round) {
+                let txs = vec![Transaction::proposed(self.id, status.round, 0, format!("node {} tx", self.id))];
+                // parents come from the quorum we advanced on, so this only fails before that
+                if let Ok(block) = self.consensus.propose_block(txs, self.id).await {
+                    sync.mark_proposed(status.round, Instant::now());
+                    // our own header counts towards its certificate too - on its own if the quorum is just us
+                    let cert = self.consensus.vote_block(&block.hash, self.id).await;
+                    self.transport.broadcast(self.id, MessagePayload::Block(block.clone())).await;
+                    if let Ok(Some(cert)) = cert {
+                        self.transport.broadcast(self.id, MessagePayload::Certificate(cert)).await;
+                    }
+                    *own_header = Some(block);
+                }
+            } else if sync.resend_due(status.round, Instant::now())
+                && let Some(block) = own_header.as_ref() {
+                // certified but the round is still stuck - the certificate may be what got lost
+                let payload = match self.consensus.certified_block(&block.hash).await {
+                    Some((_, cert)) => MessagePayload::Certificate(cert),
+                    None => MessagePayload::Block(block.clone()),
+                };
+                self.transport.broadcast(self.id, payload).await;
             }
+
+            if !sync.ready_to_advance(status, Instant::now()) {
+                return;
+            }
+            self.consensus.advance_round().await;
+            let _ = self.consensus.commit_blocks().await;
         }
     }
-}
\ No newline at end of file
+}
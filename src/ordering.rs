@@ -0,0 +1,283 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::bullshark::{Bullshark, BullsharkConfig};
+use crate::coin::{Coin, CoinPublicKeys, CoinShare, wave_value};
+use crate::dag::DAG;
+use crate::leader_schedule::{LeaderSchedule, ReputationConfig};
+use crate::tusk::Tusk;
+use crate::{Certificate, CommittedSubDag, Hash, ValidatorId, ValidatorSet, choose_leader};
+
+/*
+    Ordering engines
+
+    Narwhal builds the DAG, the ordering engine decides which leaders commit
+    and in what order. Every engine gets the same read-only view so we can
+    run Tusk, Bullshark and the old round robin rule on identical workloads
+*/
+
+// read-only snapshot of what consensus knows, handed to the engine
+pub struct DagView<'a> {
+    pub dag: &'a DAG,
+    pub certificates: &'a HashMap<Hash, Certificate>,
+    pub committed: &'a HashSet<Hash>,
+    pub validator_set: &'a ValidatorSet,
+    pub current_round: u32,
+    // sequence number the next committed sub-dag gets
+    pub next_sequence: u64,
+    // digest of the last commit, the next one chains onto it
+    pub last_digest: Hash,
+}
+
+pub trait OrderingEngine: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    // everything that can be committed right now, in order
+    fn try_commit(&mut self, view: &DagView) -> Vec<CommittedSubDag>;
+
+    // who could lead `round` as far as the engine knows - empty off leader rounds or while the coin is hidden
+    fn leaders(&self, view: &DagView, round: u32) -> Vec<ValidatorId>;
+
+    // can we leave the current round yet - only engines with leader timeouts care
+    fn leader_ready(&self, _view: &DagView) -> bool {
+        true
+    }
+
+    // how long the node should wait on leader_ready before moving on anyway
+    fn leader_timeout(&self) -> Duration {
+        Duration::ZERO
+    }
+}
+
+// which engine to run - this is what nodes/the simulator get configured with
+#[derive(Debug, Clone)]
+pub enum ConsensusMode {
+    Tusk(ReputationConfig),
+    Bullshark(BullsharkConfig),
+    RoundRobin,
+}
+
+impl Default for ConsensusMode {
+    fn default() -> Self {
+        ConsensusMode::Tusk(ReputationConfig::default())
+    }
+}
+
+impl ConsensusMode {
+    pub fn build(&self, coin: Arc<Coin>, validator_set: &ValidatorSet) -> Box<dyn OrderingEngine> {
+        match self {
+            ConsensusMode::Tusk(reputation) => Box::new(Tusk::new(coin, validator_set, reputation.clone())),
+            ConsensusMode::Bullshark(config) => Box::new(Bullshark::new(config.clone(), coin, validator_set)),
+            ConsensusMode::RoundRobin => Box::new(RoundRobin::default()),
+        }
+    }
+
+    // what the engine's leader schedule runs with - round robin never drops anyone
+    pub fn reputation(&self) -> ReputationConfig {
+        match self {
+            ConsensusMode::Tusk(reputation) => reputation.clone(),
+            ConsensusMode::Bullshark(config) => config.reputation.clone(),
+            ConsensusMode::RoundRobin => ReputationConfig { commits_per_update: 0, ..Default::default() },
+        }
+    }
+
+    /*
+        The slots the engine could hand its leader schedule for a leader in `round`,
+        with what its leader needs to commit on its own, worked out from public
+        data only so a light client can redo it (proof.rs). Bullshark has two -
+        the steady one and, with the fallback on, the coin's once the shares
+        reveal it. Keep in step with Tusk::leader and Bullshark::direct_anchor
+     */
+    pub fn leader_slots(&self, round: u32, validator_set: &ValidatorSet, coin: Option<&CoinPublicKeys>, shares: &[CoinShare]) -> Vec<LeaderSlot> {
+        let slot = |slot, votes_needed| LeaderSlot { slot, votes_needed, fallback: None };
+        match self {
+            // commits on the certificate alone
+            ConsensusMode::RoundRobin => vec![slot(round as u64, 0)],
+            _ if !round.is_multiple_of(2) => Vec::new(),
+            ConsensusMode::Tusk(_) => wave_value(coin, round / 2, round, shares).into_iter()
+                .map(|value| slot(value, validator_set.validity_threshold()))
+                .collect(),
+            ConsensusMode::Bullshark(config) => {
+                let steady_slot = (round / 2) as u64;
+                let fallback = wave_value(coin, round / 2, round, shares).filter(|_| config.fallback)
+                    .map(|value| LeaderSlot {
+                        fallback: Some((steady_slot, validator_set.threshold)),
+                        ..slot(value, validator_set.validity_threshold())
+                    });
+                std::iter::once(slot(steady_slot, config.steady_votes_needed(validator_set))).chain(fallback).collect()
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LeaderSlot {
+    pub slot: u64,
+    // next round blocks pointing at the leader
+    pub votes_needed: usize,
+    // bullshark's coin slot also needs (steady slot, this many) next round blocks that skip the steady leader
+    pub fallback: Option<(u64, usize)>,
+}
+
+impl FromStr for ConsensusMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "tusk" => Ok(ConsensusMode::default()),
+            "bullshark" => Ok(ConsensusMode::Bullshark(BullsharkConfig::default())),
+            "bullshark-fallback" => Ok(ConsensusMode::Bullshark(BullsharkConfig { fallback: true, ..Default::default() })),
+            "round-robin" => Ok(ConsensusMode::RoundRobin),
+            other => Err(format!("Unknown ordering engine {}", other)),
+        }
+    }
+}
+
+// same names FromStr takes
+impl fmt::Display for ConsensusMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConsensusMode::Tusk(_) => write!(f, "tusk"),
+            ConsensusMode::Bullshark(config) if config.fallback => write!(f, "bullshark-fallback"),
+            ConsensusMode::Bullshark(_) => write!(f, "bullshark"),
+            ConsensusMode::RoundRobin => write!(f, "round-robin"),
+        }
+    }
+}
+
+impl DagView<'_> {
+    pub fn is_certified(&self, hash: &Hash) -> bool {
+        self.certificates.get(hash)
+            .is_some_and(|cert| cert.is_valid_cert(self.validator_set))
+    }
+
+    // the author's block for the round, only if it has a valid cert
+    pub fn certified_block(&self, author: ValidatorId, round: u32) -> Option<Hash> {
+        self.dag.get_author_round_block(author, round)
+            .filter(|hash| self.is_certified(hash))
+    }
+
+    // coin shares carried by the blocks of a round
+    pub fn coin_shares(&self, round: u32) -> Vec<CoinShare> {
+        self.dag.get_round_blocks(round).iter()
+            .filter_map(|hash| self.dag.get_block(hash))
+            .filter_map(|block| block.coin_share)
+            .collect()
+    }
+
+    // how many distinct authors in the next round have a certified block pointing at the leader
+    pub fn support(&self, leader_block: &Hash) -> usize {
+        let mut voters = HashSet::new();
+
+        for child in self.dag.get_children(leader_block) {
+            if let (true, Some(block)) = (self.is_certified(&child), self.dag.get_block(&child)) {
+                voters.insert(block.author);
+            }
+        }
+
+        voters.len()
+    }
+
+    /*
+        Turn an ordered list of leaders into sub-dags: each leader takes the
+        certified part of its causal history (weak links included) that
+        nothing before it has committed
+     */
+    pub fn commit_leaders(&self, leaders: &[Hash]) -> Vec<CommittedSubDag> {
+        let mut taken: HashSet<Hash> = HashSet::new();
+        let mut sub_dags: Vec<CommittedSubDag> = Vec::new();
+
+        for leader in leaders {
+            let Some(leader_block) = self.dag.get_block(leader) else {
+                continue;
+            };
+            if self.committed.contains(leader) || !taken.insert(*leader) {
+                continue;
+            }
+
+            // get_causal_history, except a committed block's history is all committed too
+                // so the walk stops there instead of going back to genesis every commit
+            let mut blocks = vec![leader_block.clone()];
+            let mut stack = vec![*leader];
+            while let Some(current) = stack.pop() {
+                for parent in self.dag.get_parents(&current).into_iter().chain(self.dag.get_weak_parents(&current)) {
+                    if self.committed.contains(&parent) || !taken.insert(parent) {
+                        continue;
+                    }
+                    let Some(block) = self.dag.get_block(&parent) else {
+                        continue;
+                    };
+                    if self.is_certified(&parent) {
+                        blocks.push(block.clone());
+                    }
+                    stack.push(parent);
+                }
+            }
+
+            let sequence = self.next_sequence + sub_dags.len() as u64;
+            let previous = sub_dags.last().map_or(self.last_digest, |prev| prev.digest);
+            sub_dags.push(CommittedSubDag::new(sequence, leader_block, blocks).with_previous_digest(previous));
+        }
+
+        sub_dags
+    }
+
+    /*
+        commit_leaders for engines on a reputation schedule. The schedule counts
+        every commit, and once it changes the leaders after that commit were
+        picked with the old one - they're left out so the engine redoes them
+     */
+    pub fn commit_scheduled(&self, leaders: &[Hash], schedule: &mut LeaderSchedule) -> Vec<CommittedSubDag> {
+        let mut committed = Vec::new();
+        for sub_dag in self.commit_leaders(leaders) {
+            let schedule_changed = schedule.on_commit(&sub_dag);
+            committed.push(sub_dag);
+            if schedule_changed {
+                break;
+            }
+        }
+        committed
+    }
+}
+
+/*
+    The original naive rule: the round robin leader of current_round - 2
+    commits as soon as it has a certificate. No votes, no waves, no
+    reputation - kept around as a baseline to compare the real protocols against
+*/
+#[derive(Default)]
+pub struct RoundRobin {
+    last_committed_round: Option<u32>,
+}
+
+impl OrderingEngine for RoundRobin {
+    fn name(&self) -> &'static str {
+        "round-robin"
+    }
+
+    fn try_commit(&mut self, view: &DagView) -> Vec<CommittedSubDag> {
+        if view.current_round < 2 {
+            return Vec::new();
+        }
+
+        let round = view.current_round - 2;
+        if self.last_committed_round.is_some_and(|last| round <= last) {
+            return Vec::new();
+        }
+
+        let leader = choose_leader(round, view.validator_set.validators.len() as u32);
+        let Some(leader_block) = view.certified_block(leader, round) else {
+            return Vec::new();
+        };
+
+        self.last_committed_round = Some(round);
+        view.commit_leaders(&[leader_block])
+    }
+
+    fn leaders(&self, view: &DagView, round: u32) -> Vec<ValidatorId> {
+        vec![choose_leader(round, view.validator_set.validators.len() as u32)]
+    }
+}
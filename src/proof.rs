@@ -0,0 +1,413 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+
+use crate::coin::{CoinPublicKeys, CoinShare};
+use crate::consensus::ConsensusState;
+use crate::leader_schedule::LeaderSchedule;
+use crate::ordering::ConsensusMode;
+use crate::{Block, Certificate, CommittedSubDag, Hash, Transaction, ValidatorId, ValidatorSet};
+
+/*
+    Commit proofs for light clients
+
+    A light client only knows the validator set, the coin's public keys and
+    which engine the chain runs. To convince it a tx is final we hand it
+    everything needed to re-check the commit rule without a DAG:
+        - the leader header with its 2f+1 certificate
+        - the coin shares for its wave and the leader schedule at that commit,
+          so the client can redo the election and see the leader really led
+        - enough certified headers from the next round that point at the leader
+          for the engine to commit it on its own (f+1 for tusk), and the slot that
+          elected it. A bullshark coin slot needs fewer of those than a steady one
+          but also 2f+1 fallback votes, shown with all their parents so the client
+          can see none of them is the steady leader's
+        - or, for a skipped leader that only committed because a later one did,
+          that later anchor with its election and votes plus the strong path
+          from it down to the skipped leader - the same path the engine walks
+        - a chain of headers from the leader down to the block holding the tx,
+          each one a (strong or weak) parent of the one before
+        - where in that block the tx sits
+        - the sub-dag's block hashes and the digest before it, which hash to
+          the commit digest at `sequence`
+    Every header is checked against its hash so nothing can be swapped in.
+
+    NOTE: the schedule comes from every commit before this one, which the client
+    doesn't have - it only checks it's one the reputation rule could produce
+    (everyone in the set, at most f dropped). Comparing `digest` with the one
+    f+1 validators report for `sequence` pins down the rest
+*/
+
+#[derive(Debug, Clone)]
+pub struct CommitProof {
+    // sequence of the sub-dag the tx was committed in
+    pub sequence: u64,
+    pub leader: Block,
+    pub leader_certificate: Certificate,
+    // shares carried by the round + 2 blocks - empty for the deterministic coin
+    pub coin_shares: Vec<CoinShare>,
+    // None if the leader committed on its own votes, else the anchor that pulled it in
+    pub anchor: Option<AnchorProof>,
+    // next round headers voting for the anchor (the leader if there is none), with their certificates
+    pub support: Vec<(Block, Certificate)>,
+    // slot that elected the anchor (the leader if there is none) - sets how many votes it needed
+    pub slot: u64,
+    // empty unless that was a bullshark coin slot
+    pub fallback_votes: Vec<FallbackVote>,
+    // leader rotation the engine picked this leader from
+    pub schedule: Vec<ValidatorId>,
+    // leader's parent first, block holding the tx last - empty if the leader holds it
+    pub path: Vec<Block>,
+    pub inclusion: InclusionProof,
+    // every block of the sub-dag in commit order, and the digest it chains onto
+    pub blocks: Vec<Hash>,
+    pub previous_digest: Hash,
+    pub digest: Hash,
+}
+
+#[derive(Debug, Clone)]
+pub struct AnchorProof {
+    pub block: Block,
+    pub certificate: Certificate,
+    pub coin_shares: Vec<CoinShare>,
+    // strong parents only - anchor's parent first, the skipped leader's child last
+    pub path: Vec<Block>,
+}
+
+// a next round header that skipped the steady leader
+#[derive(Debug, Clone)]
+pub struct FallbackVote {
+    pub block: Block,
+    pub certificate: Certificate,
+    // every one of its strong parents
+    pub parents: Vec<Block>,
+}
+
+#[derive(Debug, Clone)]
+pub struct InclusionProof {
+    pub tx_index: usize,
+    pub tx: Transaction,
+}
+
+impl CommitProof {
+    // build a proof from a node's state for a committed tx - None if it isn't committed (yet) or we can't show why
+    pub fn build(env: &ConsensusState, validator_set: &ValidatorSet, mode: &ConsensusMode, coin: Option<&CoinPublicKeys>, tx_id: u64) -> Option<Self> {
+        let sub_dag = env.commit_log.iter()
+            .find(|sub_dag| sub_dag.transactions.iter().any(|tx| tx.id == tx_id))?;
+        let holder = sub_dag.blocks.iter()
+            .find(|block| block.txs.iter().any(|tx| tx.id == tx_id))?;
+        let tx_index = holder.txs.iter().position(|tx| tx.id == tx_id)?;
+
+        let leader = env.dag.get_block(&sub_dag.leader)?.clone();
+        let leader_certificate = env.certificates.get(&leader.hash)?.clone();
+
+        // the engines update their schedule after every commit, so replaying the log gets us theirs
+        let mut schedule = LeaderSchedule::new(validator_set, mode.reputation());
+        for earlier in &env.commit_log[..sub_dag.sequence as usize] {
+            schedule.on_commit(earlier);
+        }
+
+        let coin_shares = Self::find_coin_shares(env, &leader);
+        let mut support = Self::find_support(env, &leader.hash);
+        let mut elected_by = Self::commit_slot(env, &leader, &coin_shares, support.len(), schedule.schedule(), validator_set, mode, coin);
+        let mut anchor = None;
+        if elected_by.is_none() {
+            /*
+                skipped leader - the anchor that pulled it in went out in the same batch
+                after it, elected with the same schedule. A schedule change ends a batch
+             */
+            let mut window = schedule.clone();
+            let mut changed = window.on_commit(sub_dag);
+            for later in &env.commit_log[sub_dag.sequence as usize + 1..] {
+                if changed {
+                    break;
+                }
+                let later_leader = env.dag.get_block(&later.leader)?;
+                let later_support = Self::find_support(env, &later.leader);
+                let later_shares = Self::find_coin_shares(env, later_leader);
+                if let Some(later_slot) = Self::commit_slot(env, later_leader, &later_shares, later_support.len(), schedule.schedule(), validator_set, mode, coin)
+                    && let Some(path) = env.dag.check_path(&leader.hash, &later.leader) {
+                    anchor = Some(Self::anchor_proof(env, &path)?);
+                    support = later_support;
+                    elected_by = Some(later_slot);
+                    break;
+                }
+                changed = window.on_commit(later);
+            }
+            anchor.as_ref()?;
+        }
+
+        let (slot, fallback_votes) = elected_by?;
+        let path = Self::find_path(env, &leader, &holder.hash)?;
+
+        Some(Self {
+            sequence: sub_dag.sequence,
+            coin_shares,
+            leader,
+            leader_certificate,
+            anchor,
+            support,
+            slot,
+            fallback_votes,
+            schedule: schedule.schedule().to_vec(),
+            path,
+            inclusion: InclusionProof { tx_index, tx: holder.txs[tx_index].clone() },
+            blocks: sub_dag.block_hashes(),
+            previous_digest: sub_dag.previous_digest,
+            digest: sub_dag.digest,
+        })
+    }
+
+    // a slot electing `block` that lets it commit on `votes` votes, with the fallback votes it takes - None if none does
+    #[allow(clippy::too_many_arguments)]
+    fn commit_slot(env: &ConsensusState, block: &Block, coin_shares: &[CoinShare], votes: usize, schedule: &[ValidatorId], validator_set: &ValidatorSet, mode: &ConsensusMode, coin: Option<&CoinPublicKeys>) -> Option<(u64, Vec<FallbackVote>)> {
+        mode.leader_slots(block.round, validator_set, coin, coin_shares).into_iter()
+            .filter(|slot| votes >= slot.votes_needed && elected(schedule, slot.slot) == Some(block.author))
+            .find_map(|slot| match slot.fallback {
+                None => Some((slot.slot, Vec::new())),
+                Some((steady_slot, votes_needed)) => {
+                    let fallback_votes = Self::find_fallback_votes(env, block.round + 1, elected(schedule, steady_slot)?);
+                    (fallback_votes.len() >= votes_needed).then_some((slot.slot, fallback_votes))
+                }
+            })
+    }
+
+    // certified blocks in `round` whose parents we all have and none of which is from `steady_leader`, one per author
+    fn find_fallback_votes(env: &ConsensusState, round: u32, steady_leader: ValidatorId) -> Vec<FallbackVote> {
+        let mut authors = HashSet::new();
+        let mut votes = Vec::new();
+        let mut blocks = env.dag.get_round_blocks(round);
+        blocks.sort();
+        for hash in blocks {
+            let (Some(block), Some(certificate)) = (env.dag.get_block(&hash), env.certificates.get(&hash)) else {
+                continue;
+            };
+            let Some(parents) = block.parents.iter().map(|parent| env.dag.get_block(parent).cloned()).collect::<Option<Vec<_>>>() else {
+                continue;
+            };
+            if parents.iter().all(|parent| parent.author != steady_leader) && authors.insert(block.author) {
+                votes.push(FallbackVote { block: block.clone(), certificate: certificate.clone(), parents });
+            }
+        }
+        votes
+    }
+
+    // every certified child we know of, one per author
+    fn find_support(env: &ConsensusState, leader: &Hash) -> Vec<(Block, Certificate)> {
+        let mut authors = HashSet::new();
+        let mut support = Vec::new();
+        let mut children = env.dag.get_children(leader);
+        children.sort();
+        for child in children {
+            if let (Some(block), Some(cert)) = (env.dag.get_block(&child), env.certificates.get(&child))
+                && authors.insert(block.author) {
+                support.push((block.clone(), cert.clone()));
+            }
+        }
+        support
+    }
+
+    fn find_coin_shares(env: &ConsensusState, leader: &Block) -> Vec<CoinShare> {
+        let wave = leader.round / 2;
+        env.dag.get_round_blocks(leader.round + 2).iter()
+            .filter_map(|hash| env.dag.get_block(hash)?.coin_share)
+            .filter(|share| share.wave == wave)
+            .collect()
+    }
+
+    // `path` runs up the strong edges from the skipped leader to the anchor, both ends included
+    fn anchor_proof(env: &ConsensusState, path: &[Hash]) -> Option<AnchorProof> {
+        let (anchor, between) = path[1..].split_last()?;
+        let block = env.dag.get_block(anchor)?.clone();
+        Some(AnchorProof {
+            certificate: env.certificates.get(anchor)?.clone(),
+            coin_shares: Self::find_coin_shares(env, &block),
+            path: between.iter().rev()
+                .map(|hash| env.dag.get_block(hash).cloned())
+                .collect::<Option<_>>()?,
+            block,
+        })
+    }
+
+    // BFS down strong and weak parents - the committed history follows both
+    fn find_path(env: &ConsensusState, leader: &Block, target: &Hash) -> Option<Vec<Block>> {
+        if leader.hash == *target {
+            return Some(Vec::new());
+        }
+
+        let mut came_from: HashMap<Hash, Hash> = HashMap::new();
+        let mut queue = VecDeque::from([leader.hash]);
+        while let Some(current) = queue.pop_front() {
+            let block = env.dag.get_block(&current)?;
+            for parent in block.parents.iter().chain(&block.weak_parents) {
+                if came_from.contains_key(parent) || !env.dag.contains_block(parent) {
+                    continue;
+                }
+                came_from.insert(*parent, current);
+
+                if parent == target {
+                    // walk back up to the leader
+                    let mut path = vec![env.dag.get_block(parent)?.clone()];
+                    let mut step = current;
+                    while step != leader.hash {
+                        path.push(env.dag.get_block(&step)?.clone());
+                        step = came_from[&step];
+                    }
+                    path.reverse();
+                    return Some(path);
+                }
+                queue.push_back(*parent);
+            }
+        }
+
+        None
+    }
+
+    /*
+        Stateless check against a validator set, hands back the proven tx.
+        Nothing here touches a DAG - only hashes, parent lists and signatures.
+        `coin` is None for the deterministic coin
+     */
+    pub fn verify(&self, validator_set: &ValidatorSet, mode: &ConsensusMode, coin: Option<&CoinPublicKeys>) -> Result<&Transaction, String> {
+        // leader is certified
+        Self::check_certified(&self.leader, &self.leader_certificate, validator_set)?;
+
+        // and it was the leader: same slot and schedule lookup the engine does
+        self.check_schedule(validator_set, mode)?;
+        self.check_elected(&self.leader, &self.coin_shares, validator_set, mode, coin)?;
+
+        // committed on its own votes, or an anchor with a strong path down to it committed on them
+        let (committed_by, committed_shares) = match &self.anchor {
+            None => (&self.leader, &self.coin_shares),
+            Some(anchor) => {
+                Self::check_certified(&anchor.block, &anchor.certificate, validator_set)?;
+
+                let mut current = &anchor.block;
+                for block in &anchor.path {
+                    if !block.verify() {
+                        return Err("Anchor path block does not match its hash".to_string());
+                    }
+                    if !current.parents.contains(&block.hash) {
+                        return Err(format!("Anchor path is broken at round {}", block.round));
+                    }
+                    current = block;
+                }
+                if !current.parents.contains(&self.leader.hash) {
+                    return Err("Anchor path does not reach the leader".to_string());
+                }
+                (&anchor.block, &anchor.coin_shares)
+            }
+        };
+
+        // the slot it claims really elected it - a coin slot only with its fallback votes
+        let slot = mode.leader_slots(committed_by.round, validator_set, coin, committed_shares).into_iter()
+            .find(|slot| slot.slot == self.slot && slot.fallback.is_some() != self.fallback_votes.is_empty()
+                && elected(&self.schedule, slot.slot) == Some(committed_by.author))
+            .ok_or_else(|| format!("Slot {} does not elect the block from {} in round {}", self.slot, committed_by.author, committed_by.round))?;
+        if let Some((steady_slot, votes_needed)) = slot.fallback {
+            self.check_fallback_votes(committed_by.round + 1, steady_slot, votes_needed, validator_set)?;
+        }
+        let votes_needed = slot.votes_needed;
+
+        // commit rule: enough distinct certified next round headers point at it
+        let mut voters: HashSet<ValidatorId> = HashSet::new();
+        for (block, cert) in &self.support {
+            Self::check_certified(block, cert, validator_set)?;
+            if block.round != committed_by.round + 1 || !block.parents.contains(&committed_by.hash) {
+                return Err(format!("Block from {} in round {} does not vote for the leader", block.author, block.round));
+            }
+            voters.insert(block.author);
+        }
+        if voters.len() < votes_needed {
+            return Err(format!("Leader has {} votes - need {}", voters.len(), votes_needed));
+        }
+
+        // every step goes from a block to one of its parents
+        let mut current = &self.leader;
+        for block in &self.path {
+            if !block.verify() {
+                return Err("Path block does not match its hash".to_string());
+            }
+            if !current.parents.contains(&block.hash) && !current.weak_parents.contains(&block.hash) {
+                return Err(format!("Path is broken at round {}", block.round));
+            }
+            current = block;
+        }
+
+        // the tx is really in the last one
+        let tx = &self.inclusion.tx;
+        let found = match current.txs.get(self.inclusion.tx_index) {
+            Some(found) if found.id == tx.id && found.data == tx.data => found,
+            _ => return Err(format!("Transaction {} is not in the block", tx.id)),
+        };
+
+        // and both ends were committed at `sequence` under `digest`
+        if !self.blocks.contains(&self.leader.hash) || !self.blocks.contains(&current.hash) {
+            return Err(format!("Block is not part of commit {}", self.sequence));
+        }
+        if CommittedSubDag::chain_digest(self.previous_digest, self.sequence, &self.blocks) != self.digest {
+            return Err(format!("Blocks do not hash to the digest of commit {}", self.sequence));
+        }
+
+        Ok(found)
+    }
+
+    // a skipped leader's slot isn't recorded, any leader its round could have passes
+    fn check_elected(&self, leader: &Block, coin_shares: &[CoinShare], validator_set: &ValidatorSet, mode: &ConsensusMode, coin: Option<&CoinPublicKeys>) -> Result<(), String> {
+        let elected: Vec<ValidatorId> = mode.leader_slots(leader.round, validator_set, coin, coin_shares).into_iter()
+            .filter_map(|slot| elected(&self.schedule, slot.slot))
+            .collect();
+        if !elected.contains(&leader.author) {
+            return Err(format!("Block from {} is not the leader of round {} - {:?} could be", leader.author, leader.round, elected));
+        }
+        Ok(())
+    }
+
+    // enough certified `round` blocks from different authors, each showing every parent and none of them the steady leader's
+    fn check_fallback_votes(&self, round: u32, steady_slot: u64, votes_needed: usize, validator_set: &ValidatorSet) -> Result<(), String> {
+        let steady_leader = elected(&self.schedule, steady_slot).ok_or("Leader schedule is empty")?;
+        let mut voters: HashSet<ValidatorId> = HashSet::new();
+        for vote in &self.fallback_votes {
+            Self::check_certified(&vote.block, &vote.certificate, validator_set)?;
+            let shown: HashSet<Hash> = vote.parents.iter().filter(|parent| parent.verify()).map(|parent| parent.hash).collect();
+            if vote.block.round != round || vote.block.parents.iter().any(|parent| !shown.contains(parent)) {
+                return Err(format!("Fallback vote from {} does not show all its parents", vote.block.author));
+            }
+            if vote.parents.iter().any(|parent| vote.block.parents.contains(&parent.hash) && parent.author == steady_leader) {
+                return Err(format!("Block from {} voted for the steady leader {}", vote.block.author, steady_leader));
+            }
+            voters.insert(vote.block.author);
+        }
+        if voters.len() < votes_needed {
+            return Err(format!("Coin slot has {} fallback votes - need {}", voters.len(), votes_needed));
+        }
+        Ok(())
+    }
+
+    // sorted, no repeats, all validators, and no more than f of them dropped
+    fn check_schedule(&self, validator_set: &ValidatorSet, mode: &ConsensusMode) -> Result<(), String> {
+        let n = validator_set.validators.len();
+        let max_dropped = if mode.reputation().commits_per_update == 0 { 0 } else { validator_set.max_faulty() };
+        if self.schedule.is_empty()
+            || !self.schedule.is_sorted_by(|a, b| a < b)
+            || self.schedule.iter().any(|id| !validator_set.validators.contains_key(id))
+            || self.schedule.len() + max_dropped < n {
+            return Err("Leader schedule is not one the engine could have".to_string());
+        }
+        Ok(())
+    }
+
+    fn check_certified(block: &Block, cert: &Certificate, validator_set: &ValidatorSet) -> Result<(), String> {
+        if !block.verify() {
+            return Err("Block does not match its hash".to_string());
+        }
+        if cert.block_hash != block.hash || cert.round != block.round {
+            return Err("Certificate is for a different block".to_string());
+        }
+        cert.verify(validator_set)
+    }
+}
+
+// the same lookup LeaderSchedule::leader does - None for an empty schedule
+fn elected(schedule: &[ValidatorId], slot: u64) -> Option<ValidatorId> {
+    let index = slot.checked_rem(schedule.len() as u64)?;
+    Some(schedule[index as usize])
+}
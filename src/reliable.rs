@@ -0,0 +1,243 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::sync::Mutex;
+
+use tokio::time::{sleep_until, Duration, Instant};
+
+use crate::network::{MessagePayload, NetworkMsg};
+use crate::transport::Transport;
+use crate::ValidatorId;
+
+/*
+    Reliable point to point delivery
+
+    ReliableTransport goes on top of any other transport (simulator, tcp)
+    and turns its send-once into at-least-once, deduplicated delivery:
+        - every message to a peer gets the next sequence number and is kept
+          until the peer acks it
+        - anything not acked in time goes out again, waiting twice as long
+          each time up to max_backoff
+        - the receiver acks everything, duplicates too (the first ack may be
+          what got lost), but only hands each sequence number to the node once
+    Nothing is ordered - the protocol doesn't care what order messages come in.
+
+    Sequence numbers belong to a session, a random id the transport is made
+    with (from the simulator's seeded rng in a simulation), so a restarted
+    node doesn't get its new messages thrown away as repeats of the old ones. A peer that stays gone would make the backlog
+    grow forever, so past max_unacked the oldest message to it is dropped -
+    every message carries the oldest number still being retried so the
+    receiver knows not to wait for anything older
+*/
+
+#[derive(Debug, Clone)]
+pub struct ReliableConfig {
+    // first retransmission after this long without an ack
+    pub retransmit_after: Duration,
+    pub max_backoff: Duration,
+    // per peer, oldest unacked message is given up on past this
+    pub max_unacked: usize,
+}
+
+impl Default for ReliableConfig {
+    fn default() -> Self {
+        Self {
+            retransmit_after: Duration::from_millis(200),
+            max_backoff: Duration::from_secs(2),
+            max_unacked: 4096,
+        }
+    }
+}
+
+struct Pending {
+    due: Instant,
+    backoff: Duration,
+    payload: MessagePayload,
+}
+
+#[derive(Default)]
+struct Outbox {
+    next_seq: u64,
+    unacked: BTreeMap<u64, Pending>,
+}
+
+// what we've handed to the node from one session of one peer: everything below `below`, plus `above`
+#[derive(Default)]
+struct Delivered {
+    below: u64,
+    above: BTreeSet<u64>,
+}
+
+impl Delivered {
+    // true the first time a seq shows up
+    fn insert(&mut self, seq: u64, oldest: u64) -> bool {
+        if seq < self.below || !self.above.insert(seq) {
+            return false;
+        }
+        // the sender gave up on anything under oldest, no point waiting for it
+        self.below = self.below.max(oldest);
+        while self.above.first().is_some_and(|&first| first <= self.below) {
+            let first = self.above.pop_first().unwrap();
+            self.below = self.below.max(first + 1);
+        }
+        true
+    }
+}
+
+pub struct ReliableTransport<T: Transport> {
+    inner: T,
+    id: ValidatorId,
+    // every validator, us included
+    peers: Vec<ValidatorId>,
+    config: ReliableConfig,
+    session: u64,
+    outboxes: Mutex<HashMap<ValidatorId, Outbox>>,
+    // (peer, their session)
+    delivered: Mutex<HashMap<(ValidatorId, u64), Delivered>>,
+}
+
+impl<T: Transport> ReliableTransport<T> {
+    // session has to be new every time a node starts - see above
+    pub fn new(inner: T, id: ValidatorId, peers: Vec<ValidatorId>, config: ReliableConfig, session: u64) -> Self {
+        Self {
+            inner,
+            id,
+            peers,
+            config,
+            session,
+            outboxes: Mutex::new(HashMap::new()),
+            delivered: Mutex::new(HashMap::new()),
+        }
+    }
+
+    // messages sent but not acked yet, to anyone
+    pub fn unacked(&self) -> usize {
+        self.outboxes.lock().unwrap().values().map(|outbox| outbox.unacked.len()).sum()
+    }
+
+    fn next_retransmit(&self) -> Option<Instant> {
+        self.outboxes.lock().unwrap().values()
+            .flat_map(|outbox| outbox.unacked.values().map(|pending| pending.due))
+            .min()
+    }
+
+    // send again whatever is overdue - pushed back first, so a retransmission lost to cancellation just waits its turn
+    async fn retransmit(&self) {
+        let now = Instant::now();
+        let mut due = Vec::new();
+        {
+            let mut outboxes = self.outboxes.lock().unwrap();
+            let mut peers: Vec<_> = outboxes.iter_mut().collect();
+            // same order every run - see SimulationConfig.seed
+            peers.sort_by_key(|(peer, _)| **peer);
+            for (&to, outbox) in peers {
+                let oldest = outbox.unacked.keys().next().copied().unwrap_or(outbox.next_seq);
+                for (&seq, pending) in outbox.unacked.iter_mut().filter(|(_, pending)| pending.due <= now) {
+                    pending.backoff = (pending.backoff * 2).min(self.config.max_backoff);
+                    pending.due = now + pending.backoff;
+                    due.push(self.wrap(to, seq, oldest, pending.payload.clone()));
+                }
+            }
+        }
+        for msg in due {
+            self.inner.send(msg).await;
+        }
+    }
+
+    fn wrap(&self, to: ValidatorId, seq: u64, oldest: u64, payload: MessagePayload) -> NetworkMsg {
+        let payload = MessagePayload::Sequenced { session: self.session, seq, oldest, payload: Box::new(payload) };
+        NetworkMsg { from: self.id, to, payload }
+    }
+}
+
+impl<T: Transport + Sync> Transport for ReliableTransport<T> {
+    async fn send(&self, msg: NetworkMsg) {
+        // nothing to lose sending to ourselves
+        if msg.to == self.id {
+            self.inner.send(msg).await;
+            return;
+        }
+
+        let msg = {
+            let mut outboxes = self.outboxes.lock().unwrap();
+            let outbox = outboxes.entry(msg.to).or_default();
+            let seq = outbox.next_seq;
+            outbox.next_seq += 1;
+            outbox.unacked.insert(seq, Pending {
+                due: Instant::now() + self.config.retransmit_after,
+                backoff: self.config.retransmit_after,
+                payload: msg.payload.clone(),
+            });
+            while outbox.unacked.len() > self.config.max_unacked {
+                outbox.unacked.pop_first();
+            }
+            let oldest = *outbox.unacked.keys().next().unwrap();
+            self.wrap(msg.to, seq, oldest, msg.payload)
+        };
+        self.inner.send(msg).await;
+    }
+
+    async fn broadcast(&self, from: ValidatorId, payload: MessagePayload) {
+        for &to in self.peers.iter().filter(|&&id| id != from) {
+            self.send(NetworkMsg { from, to, payload: payload.clone() }).await;
+        }
+    }
+
+    // retransmissions go out from here, like byzantine.rs's held messages
+    async fn recv(&mut self) -> Option<NetworkMsg> {
+        loop {
+            self.retransmit().await;
+
+            let msg = match self.next_retransmit() {
+                Some(at) => tokio::select! {
+                    biased;
+                    msg = self.inner.recv() => msg,
+                    _ = sleep_until(at) => continue,
+                },
+                None => self.inner.recv().await,
+            }?;
+
+            match msg.payload {
+                MessagePayload::Ack { session, seq } => {
+                    if session == self.session
+                        && let Some(outbox) = self.outboxes.lock().unwrap().get_mut(&msg.from) {
+                        outbox.unacked.remove(&seq);
+                    }
+                }
+                MessagePayload::Sequenced { session, seq, oldest, payload } => {
+                    let ack = NetworkMsg { from: self.id, to: msg.from, payload: MessagePayload::Ack { session, seq } };
+                    // ack before marking it delivered - if we're cancelled in between, the retransmission still gets through
+                    self.inner.send(ack).await;
+                    let first = self.delivered.lock().unwrap().entry((msg.from, session)).or_default().insert(seq, oldest);
+                    if first {
+                        return Some(NetworkMsg { from: msg.from, to: msg.to, payload: *payload });
+                    }
+                }
+                // from a peer that isn't running this layer
+                payload => return Some(NetworkMsg { payload, ..msg }),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_delivered_once() {
+        let mut delivered = Delivered::default();
+        assert!(delivered.insert(0, 0));
+        assert!(delivered.insert(2, 0));
+        assert!(!delivered.insert(0, 0));
+        assert!(!delivered.insert(2, 0));
+        assert_eq!((delivered.below, delivered.above.len()), (1, 1));
+
+        assert!(delivered.insert(1, 0));
+        assert_eq!((delivered.below, delivered.above.len()), (3, 0));
+
+        // sender gave up on 3 and 4
+        assert!(delivered.insert(6, 5));
+        assert!(!delivered.insert(4, 5));
+        assert!(delivered.insert(5, 5));
+        assert_eq!((delivered.below, delivered.above.len()), (7, 0));
+    }
+}
@@ -0,0 +1,168 @@
+use tokio::time::{Duration, Instant};
+
+/*
+    Round synchronizer
+
+    A node only leaves round r once it holds certificates for round r blocks
+    from 2f+1 distinct authors - those are exactly the strong parents its
+    round r+1 header needs, so it never moves ahead of what it can build on.
+    On top of the quorum:
+        - the ordering engine can hold us in the round waiting on its leader
+          (bullshark), up to the engine's leader timeout
+        - an optional straggler timeout keeps us around a little longer after
+          the quorum so late certificates make it in as strong parents
+    and we put out exactly one header per round. If that header still isn't
+    certified after a while (lost on the way, or we were partitioned) the
+    same header goes out again - nobody can vote for what they never got.
+    Once it is certified and we're still stuck, the certificate goes out again
+*/
+
+#[derive(Debug, Clone)]
+pub struct SyncConfig {
+    // after the quorum shows up, wait at most this long for the rest of the round - None means go right away
+    pub straggler_timeout: Option<Duration>,
+    // re-broadcast our header (or its certificate) this often while we're stuck in the round - None sends it once
+    pub resend_timeout: Option<Duration>,
+}
+
+impl Default for SyncConfig {
+    fn default() -> Self {
+        Self {
+            straggler_timeout: None,
+            resend_timeout: Some(Duration::from_secs(1)),
+        }
+    }
+}
+
+// what the node knows about the round it is in
+#[derive(Debug, Clone, Copy)]
+pub struct RoundStatus {
+    pub round: u32,
+    // distinct authors with a certified block in the round
+    pub certified_authors: usize,
+    pub leader_ready: bool,
+}
+
+pub struct RoundSynchronizer {
+    config: SyncConfig,
+    quorum: usize,
+    validators: usize,
+    leader_timeout: Duration,
+    // round the timers below belong to
+    round: u32,
+    round_started: Instant,
+    quorum_at: Option<Instant>,
+    last_proposed: Option<u32>,
+    // last time our header for last_proposed went out
+    sent_at: Instant,
+}
+
+impl RoundSynchronizer {
+    pub fn new(config: SyncConfig, quorum: usize, validators: usize, leader_timeout: Duration, now: Instant) -> Self {
+        Self {
+            config,
+            quorum,
+            validators,
+            leader_timeout,
+            round: 0,
+            round_started: now,
+            quorum_at: None,
+            last_proposed: None,
+            sent_at: now,
+        }
+    }
+
+    // one header per round - true until mark_proposed has been called for it
+    pub fn needs_proposal(&self, round: u32) -> bool {
+        self.last_proposed.is_none_or(|last| round > last)
+    }
+
+    pub fn mark_proposed(&mut self, round: u32, now: Instant) {
+        self.last_proposed = Some(round);
+        self.sent_at = now;
+    }
+
+    // time to send our header for this round again - restarts the timer when it says yes
+    pub fn resend_due(&mut self, round: u32, now: Instant) -> bool {
+        let Some(timeout) = self.config.resend_timeout else {
+            return false;
+        };
+        if self.last_proposed != Some(round) || now.duration_since(self.sent_at) < timeout {
+            return false;
+        }
+        self.sent_at = now;
+        true
+    }
+
+    pub fn ready_to_advance(&mut self, status: RoundStatus, now: Instant) -> bool {
+        // new round - restart the clocks
+        if status.round != self.round {
+            self.round = status.round;
+            self.round_started = now;
+            self.quorum_at = None;
+        }
+
+        if status.certified_authors < self.quorum {
+            return false;
+        }
+        let quorum_at = *self.quorum_at.get_or_insert(now);
+
+        let leader_done = status.leader_ready || now.duration_since(self.round_started) >= self.leader_timeout;
+        let stragglers_done = match self.config.straggler_timeout {
+            None => true,
+            Some(timeout) => status.certified_authors >= self.validators || now.duration_since(quorum_at) >= timeout,
+        };
+
+        leader_done && stragglers_done
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn status(round: u32, certified_authors: usize, leader_ready: bool) -> RoundStatus {
+        RoundStatus { round, certified_authors, leader_ready }
+    }
+
+    #[test]
+    fn test_waits_for_quorum() {
+        let start = Instant::now();
+        let mut sync = RoundSynchronizer::new(SyncConfig::default(), 3, 4, Duration::ZERO, start);
+
+        assert!(sync.needs_proposal(0));
+        sync.mark_proposed(0, start);
+        assert!(!sync.needs_proposal(0));
+
+        // header goes out again every resend_timeout while we're stuck
+        assert!(!sync.resend_due(0, start + Duration::from_millis(999)));
+        assert!(sync.resend_due(0, start + Duration::from_secs(1)));
+        assert!(!sync.resend_due(0, start + Duration::from_millis(1500)));
+        assert!(sync.resend_due(0, start + Duration::from_secs(2)));
+
+        // time alone never moves the round
+        assert!(!sync.ready_to_advance(status(0, 2, true), start + Duration::from_secs(10)));
+        assert!(sync.ready_to_advance(status(0, 3, true), start + Duration::from_secs(10)));
+        assert!(sync.needs_proposal(1));
+    }
+
+    #[test]
+    fn test_leader_and_straggler_timeouts() {
+        let start = Instant::now();
+        let config = SyncConfig { straggler_timeout: Some(Duration::from_millis(100)), resend_timeout: None };
+        let mut sync = RoundSynchronizer::new(config, 3, 4, Duration::from_millis(500), start);
+        let at = |ms| start + Duration::from_millis(ms);
+
+        // quorum but no leader - hold until the leader timeout
+        assert!(!sync.ready_to_advance(status(2, 3, false), at(0)));
+        assert!(!sync.ready_to_advance(status(2, 3, false), at(499)));
+        assert!(sync.ready_to_advance(status(2, 3, false), at(500)));
+
+        // next round: leader is there, give the last validator 100ms
+        assert!(!sync.ready_to_advance(status(3, 3, true), at(600)));
+        assert!(sync.ready_to_advance(status(3, 3, true), at(700)));
+
+        // everyone certified - no reason to wait
+        assert!(sync.ready_to_advance(status(4, 4, true), at(800)));
+    }
+}
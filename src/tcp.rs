@@ -0,0 +1,238 @@
+use std::collections::{HashMap, HashSet};
+use std::io;
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc;
+use tokio::task::{JoinHandle, JoinSet};
+use tokio::time::sleep;
+
+use crate::ValidatorId;
+use crate::codec::{decode_msg, encode_msg};
+use crate::network::{MessagePayload, NetworkMsg};
+use crate::transport::Transport;
+
+/*
+    TCP transport
+
+    Same Transport as the simulator, but the messages go over real
+    sockets so every validator can be its own process:
+        - frames are a u32 (big endian) length followed by an encoded NetworkMsg (codec.rs)
+        - one outbound connection per peer, owned by a task that drains that
+          peer's queue and reconnects with exponential backoff when it drops
+        - queues are bounded - when a peer is down long enough to fill it
+          we drop new messages, same as the simulator losing packets
+        - a dialer opens with its validator id (u32, big endian) and every frame
+          on that connection has to come from it - a mismatch drops the connection.
+          The id isn't authenticated, it only stops one peer's connection from
+          speaking for everyone
+        - inbound connections just decode frames into the node's inbox, a
+          failing accept backs off the same way
+        - dropping the transport stops the listener and its connections, and
+          recv returns None once they're gone
+*/
+
+// frames bigger than this are garbage or an attack - drop the connection
+const MAX_FRAME: usize = 16 * 1024 * 1024;
+
+#[derive(Debug, Clone)]
+pub struct TcpConfig {
+    // first reconnect (or accept retry) delay, doubled after every failure
+    pub backoff_min: Duration,
+    pub backoff_max: Duration,
+    // messages buffered per peer while it's unreachable
+    pub queue_capacity: usize,
+}
+
+impl Default for TcpConfig {
+    fn default() -> Self {
+        Self {
+            backoff_min: Duration::from_millis(50),
+            backoff_max: Duration::from_secs(2),
+            queue_capacity: 1024,
+        }
+    }
+}
+
+pub struct TcpTransport {
+    id: ValidatorId,
+    queues: HashMap<ValidatorId, mpsc::Sender<Vec<u8>>>,
+    // messages to ourselves skip the socket - weak so only the listener keeps the inbox open
+    inbox: mpsc::WeakUnboundedSender<NetworkMsg>,
+    rx: mpsc::UnboundedReceiver<NetworkMsg>,
+    listener: JoinHandle<()>,
+}
+
+// bound but not started yet - lets you find out the port before handing out peer addresses
+pub struct TcpNetwork {
+    id: ValidatorId,
+    listener: TcpListener,
+    config: TcpConfig,
+}
+
+impl TcpNetwork {
+    pub async fn bind(id: ValidatorId, addr: SocketAddr, config: TcpConfig) -> io::Result<Self> {
+        Ok(Self { id, listener: TcpListener::bind(addr).await?, config })
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    // start accepting and dialing - peers that aren't up yet get retried in the background
+    pub fn start(self, peers: HashMap<ValidatorId, SocketAddr>) -> TcpTransport {
+        let (inbox, rx) = mpsc::unbounded_channel();
+
+        let mut queues = HashMap::new();
+        for (peer, addr) in peers {
+            if peer == self.id {
+                continue;
+            }
+            let (tx, queue) = mpsc::channel(self.config.queue_capacity);
+            queues.insert(peer, tx);
+            tokio::spawn(run_peer(self.id, addr, queue, self.config.clone()));
+        }
+
+        let known = queues.keys().copied().collect();
+        let weak_inbox = inbox.downgrade();
+        let listener = tokio::spawn(run_listener(self.listener, inbox, known, self.config.clone()));
+
+        TcpTransport { id: self.id, queues, inbox: weak_inbox, rx, listener }
+    }
+}
+
+impl Transport for TcpTransport {
+    async fn send(&self, msg: NetworkMsg) {
+        if msg.to == self.id {
+            if let Some(inbox) = self.inbox.upgrade() {
+                let _ = inbox.send(msg);
+            }
+            return;
+        }
+
+        // full queue means the peer has been gone a while - drop it
+        if let Some(queue) = self.queues.get(&msg.to) {
+            let _ = queue.try_send(encode_msg(&msg));
+        }
+    }
+
+    async fn broadcast(&self, from: ValidatorId, payload: MessagePayload) {
+        for &to in self.queues.keys().filter(|&&id| id != from) {
+            self.send(NetworkMsg { from, to, payload: payload.clone() }).await;
+        }
+    }
+
+    async fn recv(&mut self) -> Option<NetworkMsg> {
+        self.rx.recv().await
+    }
+}
+
+// the per-peer tasks stop on their own once the queues drop, the listener needs telling
+impl Drop for TcpTransport {
+    fn drop(&mut self) {
+        self.listener.abort();
+    }
+}
+
+async fn run_listener(listener: TcpListener, inbox: mpsc::UnboundedSender<NetworkMsg>, known: HashSet<ValidatorId>, config: TcpConfig) {
+    let mut backoff = config.backoff_min;
+    // dropped with the listener so aborting it closes every connection too
+    let mut connections = JoinSet::new();
+    loop {
+        // reap finished connections so the set doesn't grow forever
+        while connections.try_join_next().is_some() {}
+
+        // usually out of file descriptors - trying again straight away would just spin
+        let stream = match listener.accept().await {
+            Ok((stream, _)) => stream,
+            Err(err) => {
+                eprintln!("Accept failed, retrying in {:?}: {}", backoff, err);
+                sleep(backoff).await;
+                backoff = (backoff * 2).min(config.backoff_max);
+                continue;
+            }
+        };
+        backoff = config.backoff_min;
+        let inbox = inbox.clone();
+        let known = known.clone();
+        connections.spawn(async move {
+            // a bad frame or a closed socket ends this connection, the peer will redial
+            let _ = read_frames(stream, inbox, known).await;
+        });
+    }
+}
+
+async fn read_frames(mut stream: TcpStream, inbox: mpsc::UnboundedSender<NetworkMsg>, known: HashSet<ValidatorId>) -> io::Result<()> {
+    let peer = stream.read_u32().await?;
+    if !known.contains(&peer) {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, format!("Unknown peer {}", peer)));
+    }
+
+    loop {
+        let len = stream.read_u32().await? as usize;
+        if len > MAX_FRAME {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, format!("Frame of {} bytes", len)));
+        }
+        let mut frame = vec![0u8; len];
+        stream.read_exact(&mut frame).await?;
+
+        let msg = decode_msg(&frame).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+        if msg.from != peer {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, format!("Peer {} sent a message from {}", peer, msg.from)));
+        }
+        if inbox.send(msg).is_err() {
+            // node is gone
+            return Ok(());
+        }
+    }
+}
+
+// one of these per peer: keep a connection up and push the queue down it
+async fn run_peer(id: ValidatorId, addr: SocketAddr, mut queue: mpsc::Receiver<Vec<u8>>, config: TcpConfig) {
+    let mut backoff = config.backoff_min;
+    // frame that was being written when the connection died - goes out first next time
+    let mut unsent: Option<Vec<u8>> = None;
+
+    loop {
+        let mut stream = match TcpStream::connect(addr).await {
+            Ok(stream) => stream,
+            Err(_) => {
+                sleep(backoff).await;
+                backoff = (backoff * 2).min(config.backoff_max);
+                continue;
+            }
+        };
+        let _ = stream.set_nodelay(true);
+        // say who we are before anything else
+        if stream.write_u32(id).await.is_err() {
+            sleep(backoff).await;
+            backoff = (backoff * 2).min(config.backoff_max);
+            continue;
+        }
+        backoff = config.backoff_min;
+
+        loop {
+            let frame = match unsent.take() {
+                Some(frame) => frame,
+                None => match queue.recv().await {
+                    Some(frame) => frame,
+                    // every handle dropped - shut down
+                    None => return,
+                },
+            };
+
+            if write_frame(&mut stream, &frame).await.is_err() {
+                unsent = Some(frame);
+                break;
+            }
+        }
+    }
+}
+
+async fn write_frame(stream: &mut TcpStream, frame: &[u8]) -> io::Result<()> {
+    stream.write_u32(frame.len() as u32).await?;
+    stream.write_all(frame).await?;
+    stream.flush().await
+}
@@ -0,0 +1,186 @@
+use std::collections::BTreeMap;
+
+use crate::ValidatorId;
+
+/*
+    Where the nodes are
+
+    A topology puts nodes in regions and says how long a message takes
+    (a (low, high) ms range like SimulationConfig.latency_ms) from any
+    region to any other, the diagonal being within a region. A full
+    per-node matrix is the same thing with every node in a region of its
+    own. Single links can be overridden on top, one way each. Any pair
+    the topology doesn't place falls back to latency_ms. Everything goes
+    in through the constructors, place and with_link, which check the
+    ranges and regions so a lookup can't index past the matrix.
+
+    The ready-made ones use five AWS-like regions, one way latencies about
+    half the usual round trip times between them:
+        aws_spread      - round robin over all five
+        aws_one_remote  - everyone in us-east except one node in Sydney
+        aws_local_quorum - a quorum together in us-east, the rest spread out
+    The leader is one node per round, so comparing these shows what a far
+    away leader costs
+*/
+
+#[derive(Debug, Clone)]
+pub struct Topology {
+    regions: Vec<String>,
+    // region to region, indexed like regions
+    latency_ms: Vec<Vec<(u64, u64)>>,
+    // which region each node is in
+    placement: BTreeMap<ValidatorId, usize>,
+    // (from, to) links that don't go by their regions
+    links: BTreeMap<(ValidatorId, ValidatorId), (u64, u64)>,
+}
+
+pub const AWS_REGIONS: [&str; 5] = ["us-east", "us-west", "eu-west", "ap-northeast", "ap-southeast"];
+
+// one way, ms
+const AWS_LATENCY_MS: [[u64; 5]; 5] = [
+    [1, 33, 35, 73, 100],
+    [33, 1, 63, 50, 70],
+    [35, 63, 1, 105, 130],
+    [73, 50, 105, 1, 53],
+    [100, 70, 130, 53, 1],
+];
+
+impl Topology {
+    pub fn new(regions: Vec<String>, latency_ms: Vec<Vec<(u64, u64)>>) -> Result<Self, String> {
+        if latency_ms.len() != regions.len() || latency_ms.iter().any(|row| row.len() != regions.len()) {
+            return Err(format!("Latency matrix has to be {} by {}", regions.len(), regions.len()));
+        }
+        for &range in latency_ms.iter().flatten() {
+            check_range(range)?;
+        }
+        Ok(Self { regions, latency_ms, placement: BTreeMap::new(), links: BTreeMap::new() })
+    }
+
+    // a latency range for every pair of nodes - row i is node i + 1
+    pub fn matrix(latency_ms: Vec<Vec<(u64, u64)>>) -> Result<Self, String> {
+        let regions = (1..=latency_ms.len()).map(|id| format!("node {}", id)).collect();
+        let mut topology = Self::new(regions, latency_ms)?;
+        for i in 0..topology.regions.len() {
+            topology.place_at(i as ValidatorId + 1, i)?;
+        }
+        Ok(topology)
+    }
+
+    // the five regions, nobody in them yet, about 10% jitter
+    pub fn aws() -> Self {
+        let regions = AWS_REGIONS.iter().map(|name| name.to_string()).collect();
+        let latency_ms = AWS_LATENCY_MS.iter()
+            .map(|row| row.iter().map(|&ms| (ms, ms + ms / 10 + 1)).collect())
+            .collect();
+        Self::new(regions, latency_ms).unwrap()
+    }
+
+    pub fn aws_spread(ids: impl IntoIterator<Item = ValidatorId>) -> Self {
+        let mut topology = Self::aws();
+        for (i, id) in ids.into_iter().enumerate() {
+            topology.place_at(id, i % AWS_REGIONS.len()).unwrap();
+        }
+        topology
+    }
+
+    pub fn aws_one_remote(ids: impl IntoIterator<Item = ValidatorId>, remote: ValidatorId) -> Self {
+        Self::aws()
+            .place("us-east", ids).unwrap()
+            .place("ap-southeast", [remote]).unwrap()
+    }
+
+    // the first `quorum` ids in us-east, the others round robin over the other four
+    pub fn aws_local_quorum(ids: impl IntoIterator<Item = ValidatorId>, quorum: usize) -> Self {
+        let mut topology = Self::aws();
+        for (i, id) in ids.into_iter().enumerate() {
+            let region = if i < quorum { 0 } else { 1 + (i - quorum) % (AWS_REGIONS.len() - 1) };
+            topology.place_at(id, region).unwrap();
+        }
+        topology
+    }
+
+    pub fn place(mut self, region: &str, ids: impl IntoIterator<Item = ValidatorId>) -> Result<Self, String> {
+        let index = self.regions.iter().position(|name| name == region)
+            .ok_or_else(|| format!("Unknown region {}", region))?;
+        for id in ids {
+            self.place_at(id, index)?;
+        }
+        Ok(self)
+    }
+
+    fn place_at(&mut self, id: ValidatorId, region: usize) -> Result<(), String> {
+        if region >= self.regions.len() {
+            return Err(format!("Region {} out of {}", region, self.regions.len()));
+        }
+        self.placement.insert(id, region);
+        Ok(())
+    }
+
+    pub fn with_link(mut self, from: ValidatorId, to: ValidatorId, latency_ms: (u64, u64)) -> Result<Self, String> {
+        check_range(latency_ms)?;
+        self.links.insert((from, to), latency_ms);
+        Ok(self)
+    }
+
+    pub fn regions(&self) -> &[String] {
+        &self.regions
+    }
+
+    pub fn region_of(&self, id: ValidatorId) -> Option<&str> {
+        self.placement.get(&id).map(|&region| self.regions[region].as_str())
+    }
+
+    // None if either end isn't placed
+    pub fn latency_ms(&self, from: ValidatorId, to: ValidatorId) -> Option<(u64, u64)> {
+        if let Some(&latency) = self.links.get(&(from, to)) {
+            return Some(latency);
+        }
+        let (from, to) = (self.placement.get(&from)?, self.placement.get(&to)?);
+        Some(self.latency_ms[*from][*to])
+    }
+}
+
+// (low, high) gets sampled from, so it can't be upside down
+fn check_range((low, high): (u64, u64)) -> Result<(), String> {
+    if low > high {
+        return Err(format!("Latency range {}..{} ms is upside down", low, high));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_latency_lookup() {
+        let topology = Topology::aws_one_remote(1..=4, 4).with_link(1, 2, (500, 501)).unwrap();
+        assert_eq!(topology.region_of(4), Some("ap-southeast"));
+        assert_eq!(topology.latency_ms(2, 3), Some((1, 2)));
+        assert_eq!(topology.latency_ms(3, 4), Some((100, 111)));
+        // one way only
+        assert_eq!(topology.latency_ms(1, 2), Some((500, 501)));
+        assert_eq!(topology.latency_ms(2, 1), Some((1, 2)));
+        assert_eq!(topology.latency_ms(1, 5), None);
+
+        let spread = Topology::aws_spread(1..=7);
+        assert_eq!((spread.region_of(1), spread.region_of(6)), (Some("us-east"), Some("us-east")));
+        let local = Topology::aws_local_quorum(1..=7, 5);
+        assert_eq!((local.region_of(5), local.region_of(6), local.region_of(7)), (Some("us-east"), Some("us-west"), Some("eu-west")));
+
+        assert!(Topology::aws().place("mars", [1]).is_err());
+        assert!(Topology::aws().with_link(1, 2, (20, 10)).is_err());
+    }
+
+    #[test]
+    fn test_matrix() {
+        let topology = Topology::matrix(vec![
+            vec![(0, 1), (10, 20)],
+            vec![(30, 40), (0, 1)],
+        ]).unwrap();
+        assert_eq!(topology.latency_ms(1, 2), Some((10, 20)));
+        assert_eq!(topology.latency_ms(2, 1), Some((30, 40)));
+        assert!(Topology::matrix(vec![vec![(0, 1)], vec![(0, 1)]]).is_err());
+        assert!(Topology::matrix(vec![vec![(5, 1)]]).is_err());
+    }
+}
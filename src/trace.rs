@@ -0,0 +1,179 @@
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use tokio::time::{sleep_until, Instant};
+
+use crate::ValidatorId;
+use crate::codec::{decode_msg, encode_msg};
+use crate::network::NetworkMsg;
+use crate::transport::Script;
+
+/*
+    Message traces
+
+    With Simulator::with_trace the simulator writes down everything
+    that happens to every message: sent, delivered, or dropped and why.
+    The file is
+        magic "NTRACE1\0" | seed u64 | events...
+    and every event is
+        at u64 (micros since simulator start) | kind u8 | len u32 | encoded NetworkMsg (codec.rs)
+    all little endian like the codec. The seed is there so a replay can
+    rebuild the keys the run had.
+
+    replay feeds one node the messages it got in the recorded run, at the
+    times it got them - drive a Node on a ScriptedTransport with it and its
+    state machine can be stepped through on its own, with what it sends
+    coming back out of the Script to compare against the trace. Runs with
+    SimulationConfig.reliable on trace the sequenced wrappers, which a bare
+    Node ignores - put the replayed node on a ReliableTransport too
+*/
+
+const MAGIC: &[u8; 8] = b"NTRACE1\0";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TraceKind {
+    Send,
+    Deliver,
+    // packet loss
+    Lost,
+    // partitioned or a dead link - see NetworkFault
+    LinkDown,
+    // the receiver wasn't connected (crashed) when it was sent or arrived
+    Unreachable,
+}
+
+impl TraceKind {
+    fn to_u8(self) -> u8 {
+        match self {
+            TraceKind::Send => 0,
+            TraceKind::Deliver => 1,
+            TraceKind::Lost => 2,
+            TraceKind::LinkDown => 3,
+            TraceKind::Unreachable => 4,
+        }
+    }
+
+    fn from_u8(v: u8) -> Result<Self, String> {
+        match v {
+            0 => Ok(TraceKind::Send),
+            1 => Ok(TraceKind::Deliver),
+            2 => Ok(TraceKind::Lost),
+            3 => Ok(TraceKind::LinkDown),
+            4 => Ok(TraceKind::Unreachable),
+            other => Err(format!("Unknown trace event {}", other)),
+        }
+    }
+
+    pub fn is_drop(self) -> bool {
+        !matches!(self, TraceKind::Send | TraceKind::Deliver)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct TraceEvent {
+    // since the simulator started
+    pub at: Duration,
+    pub kind: TraceKind,
+    pub msg: NetworkMsg,
+}
+
+#[derive(Debug, Clone)]
+pub struct Trace {
+    pub seed: u64,
+    pub events: Vec<TraceEvent>,
+}
+
+impl Trace {
+    pub fn read(path: impl AsRef<Path>) -> Result<Self, String> {
+        let path = path.as_ref();
+        let mut bytes = Vec::new();
+        File::open(path)
+            .and_then(|file| BufReader::new(file).read_to_end(&mut bytes))
+            .map_err(|err| format!("Can't read trace {}: {}", path.display(), err))?;
+        Self::decode(&bytes)
+    }
+
+    pub fn decode(mut bytes: &[u8]) -> Result<Self, String> {
+        fn take<'a>(bytes: &mut &'a [u8], n: usize) -> Result<&'a [u8], String> {
+            if bytes.len() < n {
+                return Err("Trace cut short".to_string());
+            }
+            let (head, rest) = bytes.split_at(n);
+            *bytes = rest;
+            Ok(head)
+        }
+
+        if take(&mut bytes, MAGIC.len())? != MAGIC {
+            return Err("Not a message trace".to_string());
+        }
+        let seed = u64::from_le_bytes(take(&mut bytes, 8)?.try_into().unwrap());
+
+        let mut events = Vec::new();
+        while !bytes.is_empty() {
+            let at = Duration::from_micros(u64::from_le_bytes(take(&mut bytes, 8)?.try_into().unwrap()));
+            let kind = TraceKind::from_u8(take(&mut bytes, 1)?[0])?;
+            let len = u32::from_le_bytes(take(&mut bytes, 4)?.try_into().unwrap()) as usize;
+            let msg = decode_msg(take(&mut bytes, len)?)
+                .map_err(|err| format!("Bad message in trace event {}: {}", events.len(), err))?;
+            events.push(TraceEvent { at, kind, msg });
+        }
+        Ok(Self { seed, events })
+    }
+
+    // what `node` got, in order
+    pub fn delivered_to(&self, node: ValidatorId) -> impl Iterator<Item = &TraceEvent> {
+        self.events.iter().filter(move |event| event.kind == TraceKind::Deliver && event.msg.to == node)
+    }
+
+    // what `node` sent, in order
+    pub fn sent_by(&self, node: ValidatorId) -> impl Iterator<Item = &TraceEvent> {
+        self.events.iter().filter(move |event| event.kind == TraceKind::Send && event.msg.from == node)
+    }
+}
+
+// the simulator's end - written as the run goes
+pub struct TraceWriter {
+    out: Mutex<BufWriter<File>>,
+}
+
+impl TraceWriter {
+    pub fn create(path: impl AsRef<Path>, seed: u64) -> io::Result<Self> {
+        let mut out = BufWriter::new(File::create(path)?);
+        out.write_all(MAGIC)?;
+        out.write_all(&seed.to_le_bytes())?;
+        Ok(Self { out: Mutex::new(out) })
+    }
+
+    // a trace that stops partway is still worth having, so a failed write only loses that event
+    pub fn record(&self, at: Duration, kind: TraceKind, msg: &NetworkMsg) {
+        let encoded = encode_msg(msg);
+        let mut event = Vec::with_capacity(8 + 1 + 4 + encoded.len());
+        event.extend_from_slice(&(at.as_micros() as u64).to_le_bytes());
+        event.push(kind.to_u8());
+        event.extend_from_slice(&(encoded.len() as u32).to_le_bytes());
+        event.extend_from_slice(&encoded);
+        let _ = self.out.lock().unwrap().write_all(&event);
+    }
+
+    pub fn flush(&self) -> io::Result<()> {
+        self.out.lock().unwrap().flush()
+    }
+}
+
+/*
+    hand `node` everything it was delivered in the trace, each at the time
+    it arrived (relative to when this is called). Returns once the last one
+    is in - the node keeps running on its own after that. Proposals are
+    deterministic (see Transaction::proposed), so the node builds the same
+    headers it did in the recording and the votes in the trace certify them
+ */
+pub async fn replay(trace: &Trace, node: ValidatorId, script: &Script) {
+    let start = Instant::now();
+    for event in trace.delivered_to(node) {
+        sleep_until(start + event.at).await;
+        script.inject(event.msg.clone());
+    }
+}
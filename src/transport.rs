@@ -0,0 +1,88 @@
+use std::future::Future;
+
+use tokio::sync::mpsc;
+
+use crate::ValidatorId;
+use crate::network::{MessagePayload, NetworkMsg};
+
+/*
+    Transport: how a node talks to everyone else
+
+    Node only needs three things - point to point send, broadcast, and a
+    stream of whatever comes in - so that's the whole trait. Implemented by
+        - SimTransport (network.rs): in-process simulator with latency/loss
+        - TcpTransport (tcp.rs): real sockets, one process per validator
+        - ScriptedTransport (here): a test drives the node by hand
+    and wrapped by ReliableTransport (reliable.rs) for acks and resends on
+    top of either real one
+    The futures are Send so a node can be spawned whatever it runs on
+*/
+pub trait Transport: Send + 'static {
+    fn send(&self, msg: NetworkMsg) -> impl Future<Output = ()> + Send;
+
+    // to every peer except `from`
+    fn broadcast(&self, from: ValidatorId, payload: MessagePayload) -> impl Future<Output = ()> + Send;
+
+    // next inbound message, None once the transport is shut down - has to be cancel safe
+    fn recv(&mut self) -> impl Future<Output = Option<NetworkMsg>> + Send;
+}
+
+/*
+    Test harness: no network at all. The test injects messages into the
+    node and reads back everything the node sends, broadcasts already
+    split into one message per peer
+*/
+pub struct ScriptedTransport {
+    peers: Vec<ValidatorId>,
+    inbound: mpsc::UnboundedReceiver<NetworkMsg>,
+    outbound: mpsc::UnboundedSender<NetworkMsg>,
+}
+
+// the test's end of a ScriptedTransport
+pub struct Script {
+    to_node: mpsc::UnboundedSender<NetworkMsg>,
+    from_node: mpsc::UnboundedReceiver<NetworkMsg>,
+}
+
+pub fn scripted(peers: Vec<ValidatorId>) -> (ScriptedTransport, Script) {
+    let (to_node, inbound) = mpsc::unbounded_channel();
+    let (outbound, from_node) = mpsc::unbounded_channel();
+    (ScriptedTransport { peers, inbound, outbound }, Script { to_node, from_node })
+}
+
+impl Transport for ScriptedTransport {
+    async fn send(&self, msg: NetworkMsg) {
+        let _ = self.outbound.send(msg);
+    }
+
+    async fn broadcast(&self, from: ValidatorId, payload: MessagePayload) {
+        for &to in self.peers.iter().filter(|&&id| id != from) {
+            let _ = self.outbound.send(NetworkMsg { from, to, payload: payload.clone() });
+        }
+    }
+
+    async fn recv(&mut self) -> Option<NetworkMsg> {
+        self.inbound.recv().await
+    }
+}
+
+impl Script {
+    // hand the node a message as if it came off the network
+    pub fn inject(&self, msg: NetworkMsg) {
+        let _ = self.to_node.send(msg);
+    }
+
+    // next thing the node sent, waits for it
+    pub async fn next_sent(&mut self) -> Option<NetworkMsg> {
+        self.from_node.recv().await
+    }
+
+    // everything sent so far without waiting
+    pub fn drain_sent(&mut self) -> Vec<NetworkMsg> {
+        let mut sent = Vec::new();
+        while let Ok(msg) = self.from_node.try_recv() {
+            sent.push(msg);
+        }
+        sent
+    }
+}
@@ -0,0 +1,111 @@
+use std::sync::Arc;
+
+use crate::coin::Coin;
+use crate::leader_schedule::{LeaderSchedule, ReputationConfig};
+use crate::ordering::{DagView, OrderingEngine};
+use crate::{CommittedSubDag, Hash, ValidatorId, ValidatorSet};
+
+/*
+    Tusk commit rule:
+    wave w covers rounds 2w, 2w+1, 2w+2 (the last round of a wave is the
+    first round of the next). The leader is a block from round 2w but we
+    only look for it once we reach round 2w+2, and we only commit it if
+    f+1 certified blocks in round 2w+1 point at it
+ */
+pub struct Tusk {
+    coin: Arc<Coin>,
+    // the coin picks a slot, the schedule says who holds it
+    schedule: LeaderSchedule,
+    // last wave whose leader we committed - waves are only ever decided once
+    last_committed_wave: Option<u32>,
+}
+
+// wave w starts at round 2w - that is where its leader lives
+pub fn wave_leader_round(wave: u32) -> u32 {
+    2 * wave
+}
+
+impl Tusk {
+    pub fn new(coin: Arc<Coin>, validator_set: &ValidatorSet, reputation: ReputationConfig) -> Self {
+        Self {
+            coin,
+            schedule: LeaderSchedule::new(validator_set, reputation),
+            last_committed_wave: None,
+        }
+    }
+
+    // who leads the wave, once the coin is revealed
+    pub fn wave_leader(&self, view: &DagView, wave: u32) -> Option<ValidatorId> {
+        let leader_round = wave_leader_round(wave);
+
+        // coin shares for this wave ride on the round 2w+2 blocks
+        let shares = view.coin_shares(leader_round + 2);
+        Some(self.schedule.leader(self.coin.wave_value(wave, leader_round, &shares)?))
+    }
+
+    // leader block of the wave, once the coin is revealed and the block is certified
+    pub fn leader(&self, view: &DagView, wave: u32) -> Option<Hash> {
+        let leader = self.wave_leader(view, wave)?;
+
+        // find the block that the leader proposed - if it has a valid cert
+        view.certified_block(leader, wave_leader_round(wave))
+    }
+}
+
+impl OrderingEngine for Tusk {
+    fn name(&self) -> &'static str {
+        "tusk"
+    }
+
+    fn try_commit(&mut self, view: &DagView) -> Vec<CommittedSubDag> {
+        if view.current_round < 2 {
+            return Vec::new();
+        }
+
+        let wave = view.current_round / 2 - 1;
+        if self.last_committed_wave.is_some_and(|last| wave <= last) {
+            return Vec::new();
+        }
+
+        let Some(leader_block) = self.leader(view, wave) else {
+            return Vec::new();
+        };
+        if view.support(&leader_block) < view.validator_set.validity_threshold() {
+            return Vec::new();
+        }
+
+        /*
+            skipped leaders: walk back through the waves we never committed.
+            If there is a (strong) path from the leader we are about to commit
+            to an older leader then every honest node will eventually see it
+            too, so it has to be ordered first
+         */
+        let mut leaders = vec![leader_block];
+        let mut current = leader_block;
+        let first_open = self.last_committed_wave.map_or(0, |last| last + 1);
+        for prev_wave in (first_open..wave).rev() {
+            if let Some(prev_leader) = self.leader(view, prev_wave)
+                && view.dag.check_path(&prev_leader, &current).is_some() {
+                leaders.push(prev_leader);
+                current = prev_leader;
+            }
+        }
+        self.last_committed_wave = Some(wave);
+
+        // oldest leader first, each with its causal history
+        leaders.reverse();
+        let committed = view.commit_scheduled(&leaders, &mut self.schedule);
+        // stopped early on a schedule change - the later waves get another go
+        if let Some(last) = committed.last() {
+            self.last_committed_wave = Some(last.round / 2);
+        }
+        committed
+    }
+
+    fn leaders(&self, view: &DagView, round: u32) -> Vec<ValidatorId> {
+        if !round.is_multiple_of(2) {
+            return Vec::new();
+        }
+        self.wave_leader(view, round / 2).into_iter().collect()
+    }
+}
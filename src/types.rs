@@ -1,12 +1,15 @@
 use sha2::{Sha256, Digest};
 use std::sync::atomic::{AtomicU64, Ordering};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+
+use crate::coin::CoinShare;
+use crate::keys::{SigningKey, verify_signature};
 
 //use crate::validator;
 
 pub type Hash = [u8; 32];
 pub type ValidatorId = u32;
-pub type Signature = [u8; 64];
+pub type Signature = [u8; 16];
 
 #[derive(Debug, Clone)]
 pub struct Transaction {
@@ -18,9 +21,14 @@ pub struct Transaction {
 pub struct Block {
     pub hash: Hash,
     pub txs: Vec<Transaction>,
+    // strong parents: 2f+1 certified blocks from round - 1
     pub parents: Vec<Hash>,
+    // weak links: older certified blocks that no strong parent reaches
+    pub weak_parents: Vec<Hash>,
     pub author: ValidatorId,
     pub round: u32,
+    // round 2w+2 blocks carry the author's coin share for wave w
+    pub coin_share: Option<CoinShare>,
 }
 
 // what should my certificate have?
@@ -34,6 +42,26 @@ pub struct Certificate {
     pub signatures: Vec<(ValidatorId, Signature)>
 }
 
+/*
+    What a single commit hands out: the leader plus everything it newly
+    orders, sorted by (round, author, hash) so every honest node produces
+    exactly the same sequence. The leader always ends up last since its
+    whole history is from earlier rounds
+*/
+#[derive(Debug, Clone)]
+pub struct CommittedSubDag {
+    pub sequence: u64,
+    pub leader: Hash,
+    pub round: u32,
+    pub blocks: Vec<Block>,
+    // every tx from blocks, in block order
+    pub transactions: Vec<Transaction>,
+    // digest of the commit before this one - all zeros for the first
+    pub previous_digest: Hash,
+    // H(previous_digest, sequence, ordered block hashes) - equal digests means equal history
+    pub digest: Hash,
+}
+
 // what should vote have
     // who voted
     // does the vote need to be hashed?
@@ -54,6 +82,8 @@ pub struct Vote {
 pub struct ValidatorSet {
     pub validators: HashMap<ValidatorId,ValidatorInfo>,
     pub threshold: usize,
+    // vote signing keys - empty until keys::deal_keys fills them in
+    pub public_keys: HashMap<ValidatorId, u64>,
 }
 
 // ValidatorInfo
@@ -75,14 +105,30 @@ impl Block {
             hash: [0; 32],
             txs,
             parents,
+            weak_parents: Vec::new(),
             author,
             round,
+            coin_share: None,
         };
 
         block.hash = block.hash_fn();
         block
 
     }
+
+    // attach weak links after the fact - need to rehash since they are part of the block
+    pub fn with_weak_parents(mut self, weak_parents: Vec<Hash>) -> Self {
+        self.weak_parents = weak_parents;
+        self.hash = self.hash_fn();
+        self
+    }
+
+    pub fn with_coin_share(mut self, share: CoinShare) -> Self {
+        self.coin_share = Some(share);
+        self.hash = self.hash_fn();
+        self
+    }
+    #[allow(clippy::needless_borrows_for_generic_args)]
     pub fn hash_fn(&self) -> Hash {
         let mut hasher = Sha256::new();
 
@@ -95,23 +141,43 @@ impl Block {
         
         // how do I separate the transactions from the metdata
             // how do I hash a Vec?
+        // every vec and string gets its length first, same as codec.rs - otherwise bytes can
+            // slide from one field into the next and two different blocks hash the same
+        hasher.update((self.txs.len() as u32).to_le_bytes());
         for tx in &self.txs {
             hasher.update(&tx.id.to_le_bytes());
+            hasher.update((tx.data.len() as u32).to_le_bytes());
             hasher.update(tx.data.as_bytes());
         }
 
         // parent is a Vec<Hash> where Hash is [u8; 32]
             // do I need to own these Hashes? no
+        hasher.update((self.parents.len() as u32).to_le_bytes());
         for parent in &self.parents{
             // these are already hashes - do I need to rehash them? no
             hasher.update(parent);
         }
+
+        // weak links - same, so they can't be confused with strong parents
+        hasher.update((self.weak_parents.len() as u32).to_le_bytes());
+        for weak in &self.weak_parents {
+            hasher.update(weak);
+        }
                 
         // add the round and the author to the hasher
             // this is metadata hashing
         hasher.update(&self.round.to_le_bytes());
         hasher.update(&self.author.to_le_bytes());
 
+        hasher.update([self.coin_share.is_some() as u8]);
+        if let Some(share) = &self.coin_share {
+            hasher.update(share.wave.to_le_bytes());
+            hasher.update(share.signer.to_le_bytes());
+            hasher.update(share.value.to_le_bytes());
+            hasher.update(share.challenge.to_le_bytes());
+            hasher.update(share.response.to_le_bytes());
+        }
+
         // we finalize the hash and use into() to convert to Hash type
             // hashed everything
         hasher.finalize().into()
@@ -119,6 +185,7 @@ impl Block {
     }
     //TODO:
         // is_genesis (parents is empty?)
+    #[allow(clippy::needless_return)]
     pub fn is_genesis(&self) -> bool {
         if self.parents.is_empty() {
             return true
@@ -142,16 +209,20 @@ impl Block {
         self.hash == self.hash_fn()
     }
         // size_bytes (network limits?)
+        // exactly what codec.rs writes for it - vectors and strings get a u32 length first
     pub fn size_bytes(&self) -> usize {
         32 + // hash is 32 bytes
-        // for each transation it has some bytes
+        4 + // how many txs
+        // for each transation its id, length and bytes
         self.txs.iter()
-            .map(|tx| 8 + tx.data.len())
+            .map(|tx| 8 + 4 + tx.data.len())
             .sum::<usize>()
             +
-        self.parents.len() * 32 + // each parent is a Hash of 32 bytes
+        4 + self.parents.len() * 32 + // each parent is a Hash of 32 bytes
+        4 + self.weak_parents.len() * 32 + // same for weak links
         4 + // author 
-        4 // round
+        4 + // round
+        1 + self.coin_share.map_or(0, |_| 4 + 4 + 8 * 3) // flag, then wave, signer, value + proof
     }
 }
 
@@ -165,7 +236,71 @@ impl Transaction {
             data,
         }
     }
-    
+
+    /*
+        the index-th tx `author` proposes in `round` - the id only depends on
+        where it was proposed, not on what else ran in the process, so a
+        seeded run gets the same ids (and block hashes) every time. Top bit
+        set so it can't collide with a counter id
+     */
+    pub fn proposed(author: ValidatorId, round: u32, index: u16, data: String) -> Self {
+        Self {
+            id: 1 << 63 | (author as u64 & 0x7fff) << 48 | (round as u64) << 16 | index as u64,
+            data,
+        }
+    }
+}
+
+impl CommittedSubDag {
+    pub fn new(sequence: u64, leader: &Block, mut blocks: Vec<Block>) -> Self {
+        blocks.sort_by_key(|b| (b.round, b.author, b.hash));
+        let transactions = blocks.iter()
+            .flat_map(|b| b.txs.iter().cloned())
+            .collect();
+
+        let mut sub_dag = Self {
+            sequence,
+            leader: leader.hash,
+            round: leader.round,
+            blocks,
+            transactions,
+            previous_digest: [0; 32],
+            digest: [0; 32],
+        };
+        sub_dag.digest = sub_dag.digest_fn();
+        sub_dag
+    }
+
+    // chain onto the commit before - need to redo the digest since it covers the previous one
+    pub fn with_previous_digest(mut self, previous_digest: Hash) -> Self {
+        self.previous_digest = previous_digest;
+        self.digest = self.digest_fn();
+        self
+    }
+
+    pub fn digest_fn(&self) -> Hash {
+        Self::chain_digest(self.previous_digest, self.sequence, self.blocks.iter().map(|block| &block.hash))
+    }
+
+    // the digest from its parts - commit proofs (proof.rs) redo it from nothing but the block hashes
+    pub fn chain_digest<'a>(previous_digest: Hash, sequence: u64, blocks: impl IntoIterator<Item = &'a Hash>) -> Hash {
+        let mut hasher = Sha256::new();
+        hasher.update(previous_digest);
+        hasher.update(sequence.to_le_bytes());
+        for hash in blocks {
+            hasher.update(hash);
+        }
+        hasher.finalize().into()
+    }
+
+    // digest matches the blocks and the link it claims
+    pub fn verify_digest(&self) -> bool {
+        self.digest == self.digest_fn()
+    }
+
+    pub fn block_hashes(&self) -> Vec<Hash> {
+        self.blocks.iter().map(|b| b.hash).collect()
+    }
 }
 
 impl ValidatorSet {
@@ -184,8 +319,44 @@ impl ValidatorSet {
         Self {
             validators: validator_map,
             threshold,
+            public_keys: HashMap::new(),
         }
     }
+
+    // f - how many faulty validators we can tolerate
+    pub fn max_faulty(&self) -> usize {
+        self.validators.len().saturating_sub(1) / 3
+    }
+
+    // f + 1 - at least one honest validator is in any set this big
+    pub fn validity_threshold(&self) -> usize {
+        self.max_faulty() + 1
+    }
+
+    // false for anyone without a public key
+    pub fn verify_signature(&self, id: ValidatorId, block_hash: &Hash, signature: &Signature) -> bool {
+        self.public_keys.get(&id).is_some_and(|&key| verify_signature(key, block_hash, signature))
+    }
+}
+
+impl Vote {
+    pub fn new(block_hash: Hash, round: u32, key: &SigningKey) -> Self {
+        Self {
+            block_hash,
+            round,
+            voter: key.id,
+            signature: key.sign(&block_hash),
+        }
+    }
+
+    // checked against the voter's public key from the set
+    pub fn verify(&self, validator_set: &ValidatorSet) -> bool {
+        validator_set.verify_signature(self.voter, &self.block_hash, &self.signature)
+    }
+
+    pub fn size_bytes(&self) -> usize {
+        32 + 4 + 4 + 16 // block hash, round, voter, signature
+    }
 }
 
 impl Certificate {
@@ -203,6 +374,7 @@ impl Certificate {
 
     // check if we have enough valid signatures
         // use a ref since dont want to take ownership from hashmap
+    #[allow(clippy::needless_return, unused_variables)]
     pub fn is_valid_cert(&self, validator_set: &ValidatorSet) -> bool{
         // check if cert has enough signatures from valid validators from the set
         let mut count = 0;
@@ -218,6 +390,33 @@ impl Certificate {
 
         return false
     }
+
+    // full check for a certificate that came over the network - every signature has to hold up
+    pub fn verify(&self, validator_set: &ValidatorSet) -> Result<(), String> {
+        let mut signers = HashSet::new();
+        for (id, signature) in &self.signatures {
+            if !validator_set.validators.contains_key(id) {
+                return Err(format!("Certificate signed by unknown validator {}", id));
+            }
+            if !validator_set.verify_signature(*id, &self.block_hash, signature) {
+                return Err(format!("Invalid signature from validator {}", id));
+            }
+            if !signers.insert(*id) {
+                return Err(format!("Duplicate signature from validator {}", id));
+            }
+        }
+
+        if signers.len() < validator_set.threshold {
+            return Err(format!("Certificate has {} signatures - need {}", signers.len(), validator_set.threshold));
+        }
+
+        Ok(())
+    }
+
+    pub fn size_bytes(&self) -> usize {
+        32 + 4 + 4 + // block hash, round, how many signatures
+        self.signatures.len() * (4 + 16) // who signed and the signature
+    }
 }
 
 #[cfg(test)]
@@ -261,11 +460,20 @@ mod tests {
         let validators_set = create_validators();
         let mut cert = Certificate::new([1u8; 32], 1);
 
-        cert.add_signature(1, [1u8; 64]);
-        cert.add_signature(2, [2u8; 64]);
-        cert.add_signature(3, [3u8; 64]);
+        cert.add_signature(1, [1u8; 16]);
+        cert.add_signature(2, [2u8; 16]);
+        cert.add_signature(3, [3u8; 16]);
 
         assert!(cert.is_valid_cert(&validators_set));
     }
 
+    #[test]
+    fn test_hash_keeps_fields_apart() {
+        // without lengths these two are the same bytes: a tx whose data is a hash vs an empty tx and that hash as a parent
+        let parent = [7u8; 32];
+        let in_tx = Block::new(vec![Transaction { id: 1, data: String::from_utf8(parent.to_vec()).unwrap() }], vec![], 1, 1);
+        let in_parents = Block::new(vec![Transaction { id: 1, data: String::new() }], vec![parent], 1, 1);
+        assert_ne!(in_tx.hash, in_parents.hash);
+    }
+
 }
\ No newline at end of file
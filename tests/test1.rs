@@ -1,17 +1,92 @@
-use narwhal_tusk::consensus::{ConsensusHandle, choose_leader};
-use narwhal_tusk::types::{ValidatorInfo, ValidatorSet, Transaction};
+use narwhal_tusk::coin::{Coin, deal_coin};
+use narwhal_tusk::keys::{SigningKey, deal_keys};
+use narwhal_tusk::bullshark::BullsharkConfig;
+use narwhal_tusk::leader_schedule::ReputationConfig;
+use narwhal_tusk::consensus::{ConsensusHandle, VoteError, WEAK_LINK_DEPTH, choose_leader};
+use narwhal_tusk::ordering::ConsensusMode;
+use narwhal_tusk::byzantine::{Behavior, ByzantineTransport};
+use narwhal_tusk::churn::Cluster;
+use narwhal_tusk::network::{Bandwidth, Crash, MessagePayload, NetworkFault, NetworkMsg, Restart, SimulationConfig, Simulator};
+use narwhal_tusk::tcp::{TcpConfig, TcpNetwork};
+use narwhal_tusk::codec::encode_msg;
+use narwhal_tusk::transport::{Script, Transport, scripted};
+use narwhal_tusk::node::Node;
+use narwhal_tusk::topology::Topology;
+use narwhal_tusk::trace::{Trace, TraceKind, replay};
+use narwhal_tusk::reliable::{ReliableConfig, ReliableTransport};
+use narwhal_tusk::types::{Block, Hash, CommittedSubDag, ValidatorInfo, ValidatorSet, Transaction, Vote};
+use std::collections::HashMap;
+use std::ops::RangeInclusive;
+use futures::StreamExt;
+
+// dealer seed for the test validators' vote keys
+const KEY_SEED: u64 = 11;
 
 fn make_validator_set(n: u32) -> ValidatorSet {
     let vals = (1..=n)
         .map(|id| ValidatorInfo { id, stake: 1 })
         .collect();
-    ValidatorSet::new(vals)
+    let mut vset = ValidatorSet::new(vals);
+    deal_keys(&mut vset, KEY_SEED);
+    vset
+}
+
+// the secret halves of make_validator_set's keys
+fn signing_keys(vset: &ValidatorSet) -> HashMap<u32, SigningKey> {
+    deal_keys(&mut vset.clone(), KEY_SEED)
+}
+
+// the test plays every validator so the handle gets to sign for all of them
+fn with_all_keys(c: ConsensusHandle) -> ConsensusHandle {
+    let keys = signing_keys(c.validator_set());
+    c.with_keys(keys.into_values())
+}
+
+// validators 1..=n on the simulator, started and registered
+async fn start_cluster(sim: Simulator, n: u32) -> Cluster {
+    let vset = make_validator_set(n);
+    let mode = sim.config().mode.clone();
+    let keys = signing_keys(&vset);
+    let mut cluster = Cluster::new(sim, std::sync::Arc::new(move |id| ConsensusHandle::with_mode(vset.clone(), Coin::Deterministic, mode.clone()).with_keys([keys[&id].clone()])));
+    cluster.start(1..=n).await;
+    cluster
+}
+
+// the nodes' commit logs are the same as far as the shortest goes - returns how far that is
+async fn assert_logs_agree(cluster: &Cluster, ids: RangeInclusive<u32>, label: &str) -> u64 {
+    let nodes: Vec<(u32, &ConsensusHandle)> = ids.map(|id| (id, cluster.consensus(id).unwrap())).collect();
+    let mut shortest = u64::MAX;
+    for (_, c) in &nodes {
+        shortest = shortest.min(c.commit_count().await);
+    }
+    for sequence in 0..shortest {
+        let digest = nodes[0].1.commit_digest(sequence).await;
+        for (id, c) in &nodes[1..] {
+            assert_eq!(c.commit_digest(sequence).await, digest, "{}: node {} split at {}", label, id, sequence);
+        }
+    }
+    shortest
+}
+
+// every author proposes in the current round and every validator votes on all of them
+async fn full_round(c: &mut ConsensusHandle, authors: RangeInclusive<u32>, voters: u32) -> Vec<Block> {
+    let mut blocks = Vec::new();
+    for author in authors {
+        blocks.push(c.propose_block(vec![Transaction::new(format!("tx from {}", author))], author).await.unwrap());
+    }
+    for block in &blocks {
+        for v in 1..=voters {
+            c.vote_block(&block.hash, v).await.unwrap();
+        }
+    }
+    blocks
 }
 
 /*
     Simple test with 4 nodes, multiple rounds
 
-    go to round 2 to force commit
+    wave 0 is rounds 0,1,2 - the round 0 leader is only looked at in round 2
+    and needs f+1 certified round 1 blocks pointing at it
 
 */
 
@@ -19,17 +94,14 @@ fn make_validator_set(n: u32) -> ValidatorSet {
 async fn commits_leader_after_two_rounds() {
 
     let vset = make_validator_set(4);
-    let mut c = ConsensusHandle::new(vset);
+    let mut c = with_all_keys(ConsensusHandle::new(vset));
 
     // since we're at round 0 round%voters should be 1 (1 + round%voter)
     assert_eq!(choose_leader(0, 4), 1);
 
-    // Round 0, author 1 proposes, all vote
-    let b0 = c.propose_block(vec![Transaction::new("r0".into())], 1).await.unwrap();
-    
-    for v in 1..=4 { 
-        c.vote_block(&b0.hash, v).await.unwrap(); 
-    }
+    // Round 0, everyone proposes, all vote
+    let round0 = full_round(&mut c, 1..=4, 4).await;
+    let b0 = &round0[0];
     
     assert!(c.cert_is_valid(&b0.hash).await, "cert should be valid after quorum");
 
@@ -37,26 +109,53 @@ async fn commits_leader_after_two_rounds() {
     let committed0 = c.commit_blocks().await;
     assert!(committed0.is_empty(), "no commit at round 0");
 
-    // Round 1
+    // Round 1 - these are the votes for the leader
     c.advance_round().await;
+    full_round(&mut c, 1..=4, 4).await;
     let committed1 = c.commit_blocks().await;
     assert!(committed1.is_empty(), "no commit at round 1");
 
     // Round 2 - Commit
     c.advance_round().await;
     let committed2 = c.commit_blocks().await;
-    assert!(committed2.contains(&b0.hash), "leader of r0 should commit at r2");
+    assert!(committed2.iter().any(|d| d.leader == b0.hash), "leader of r0 should commit at r2");
 
-    // Round 3 - no new:
+    // wave 0 is done - no new:
     let committed3 = c.commit_blocks().await;
-    assert!(committed3.is_empty(), "round 3 should have nothing");
+    assert!(committed3.is_empty(), "wave 0 should not commit twice");
+}
+
+#[tokio::test]
+async fn leader_without_support_is_skipped() {
+    let vset = make_validator_set(4);
+    let mut c = with_all_keys(ConsensusHandle::new(vset));
+
+    // leader (author 1) is certified late so no round 1 block points at it
+    let mut round0 = Vec::new();
+    for author in 1..=4 {
+        round0.push(c.propose_block(vec![], author).await.unwrap());
+    }
+    for block in &round0[1..] {
+        for v in 1..=4 {
+            c.vote_block(&block.hash, v).await.unwrap();
+        }
+    }
+
+    c.advance_round().await;
+    full_round(&mut c, 1..=4, 4).await;
+    for v in 1..=4 {
+        c.vote_block(&round0[0].hash, v).await.unwrap();
+    }
+
+    c.advance_round().await;
+    assert!(c.commit_blocks().await.is_empty(), "leader has no f+1 support");
 }
 
 #[tokio::test]
 async fn reject_invalid_voter() {
     let vset = make_validator_set(4);
 
-    let mut c = ConsensusHandle::new(vset);
+    let mut c = with_all_keys(ConsensusHandle::new(vset));
 
     let b0 = c.propose_block(vec![Transaction::new("valid voter".into())], 4).await.unwrap();
     
@@ -65,4 +164,1662 @@ async fn reject_invalid_voter() {
 
     // shoudl return error
     assert!(invalid_vote.contains("Not a valid voter - not in validator set"));
-}
\ No newline at end of file
+}
+
+/*
+    4 nodes, author 4 is slow in round 0 so round 1 only points at 1..3.
+    Once 4's block is certified the round 2 proposal should weak link it
+*/
+#[tokio::test]
+async fn weak_links_pick_up_orphans() {
+    let vset = make_validator_set(4);
+    let mut c = with_all_keys(ConsensusHandle::new(vset));
+
+    let mut round0 = Vec::new();
+    for author in 1..=4 {
+        round0.push(c.propose_block(vec![Transaction::new("r0".into())], author).await.unwrap());
+    }
+    for block in &round0[..3] {
+        for v in 1..=3 {
+            c.vote_block(&block.hash, v).await.unwrap();
+        }
+    }
+
+    // round 1 - strong parents only from certified blocks
+    c.advance_round().await;
+    let mut round1 = Vec::new();
+    for author in 1..=3 {
+        let block = c.propose_block(vec![], author).await.unwrap();
+        assert_eq!(block.parent_count(), 3);
+        assert!(block.weak_parents.is_empty());
+        round1.push(block);
+    }
+    for block in &round1 {
+        for v in 1..=3 {
+            c.vote_block(&block.hash, v).await.unwrap();
+        }
+    }
+
+    // slow block finally gets its certificate
+    let slow = &round0[3];
+    for v in 1..=3 {
+        c.vote_block(&slow.hash, v).await.unwrap();
+    }
+
+    c.advance_round().await;
+    let block = c.propose_block(vec![], 1).await.unwrap();
+    assert_eq!(block.parent_count(), 3);
+    assert_eq!(block.weak_parents, vec![slow.hash]);
+}
+
+// a block certified only after a later leader committed still gets linked and ordered with the next leader
+#[tokio::test]
+async fn weak_links_reach_below_last_commit() {
+    let vset = make_validator_set(4);
+    let mut c = with_all_keys(ConsensusHandle::new(vset));
+
+    let slow = c.propose_block(vec![], 4).await.unwrap();
+    full_round(&mut c, 1..=3, 3).await;
+    for _ in 1..=2 {
+        c.advance_round().await;
+        full_round(&mut c, 1..=3, 3).await;
+    }
+    let committed = c.commit_blocks().await;
+    assert_eq!(committed.iter().map(|d| d.round).collect::<Vec<_>>(), vec![0]);
+    assert!(!committed[0].block_hashes().contains(&slow.hash));
+
+    // votes come in late - the round 0 commit has already gone without it
+    for v in 1..=3 {
+        c.vote_block(&slow.hash, v).await.unwrap();
+    }
+
+    c.advance_round().await;
+    let blocks = full_round(&mut c, 1..=3, 3).await;
+    assert!(blocks.iter().all(|block| block.weak_parents == vec![slow.hash]));
+
+    // the round 4 leader is the first one with the link in its history
+    let mut committed = Vec::new();
+    for _ in 4..=6 {
+        c.advance_round().await;
+        full_round(&mut c, 1..=3, 3).await;
+        committed.extend(c.commit_blocks().await);
+    }
+    let sub_dag = committed.iter().find(|sub_dag| sub_dag.block_hashes().contains(&slow.hash)).unwrap();
+    assert_eq!(sub_dag.round, 4);
+}
+
+// the author's header is still waiting for votes when a leader commits - they still certify it
+#[tokio::test]
+async fn late_votes_certify_after_commit() {
+    let vset = make_validator_set(4);
+    let mut c = with_all_keys(ConsensusHandle::new(vset));
+
+    let own = c.propose_block(vec![], 4).await.unwrap();
+    full_round(&mut c, 1..=3, 3).await;
+    for _ in 1..=2 {
+        c.advance_round().await;
+        full_round(&mut c, 1..=3, 3).await;
+    }
+    assert!(!c.commit_blocks().await.is_empty());
+    assert!(c.is_pending(&own.hash).await);
+
+    let mut cert = None;
+    for v in 1..=3 {
+        let vote = c.create_vote(&own.hash, v).await.unwrap();
+        cert = cert.or(c.add_vote(vote).await.unwrap());
+    }
+    assert!(cert.is_some());
+    assert!(c.dag_contains(&own.hash).await);
+}
+
+// a certified header points at a parent we haven't got - commits wait on it however long that takes, then catch up
+#[tokio::test]
+async fn missing_parent_holds_commits_until_fetched() {
+    let vset = make_validator_set(4);
+    let mut source = with_all_keys(ConsensusHandle::new(vset.clone()));
+    let mut behind = ConsensusHandle::new(vset);
+
+    let mut rounds = Vec::new();
+    for round in 0..=WEAK_LINK_DEPTH + 4 {
+        if round > 0 {
+            source.advance_round().await;
+        }
+        rounds.push(full_round(&mut source, 1..=4, 4).await);
+    }
+    assert!(!source.commit_blocks().await.is_empty());
+
+    // everything reaches behind except one round 0 header, well past the weak link depth
+    let hole = rounds[0][1].hash;
+    for (round, blocks) in rounds.iter().enumerate() {
+        if round > 0 {
+            behind.advance_round().await;
+        }
+        for block in blocks.iter().filter(|b| b.hash != hole) {
+            let (block, cert) = source.certified_block(&block.hash).await.unwrap();
+            behind.accept_block(block).await.unwrap();
+            behind.accept_certificate(cert).await.unwrap();
+        }
+        assert!(behind.commit_blocks().await.is_empty(), "committed over a hole in round {}", round);
+    }
+    assert_eq!(behind.missing_parents().await, vec![hole]);
+
+    // the fetch finally comes through - same log as the source
+    let (block, cert) = source.certified_block(&hole).await.unwrap();
+    behind.accept_block(block).await.unwrap();
+    behind.accept_certificate(cert).await.unwrap();
+    assert!(!behind.commit_blocks().await.is_empty());
+    assert!(behind.missing_parents().await.is_empty());
+    assert_eq!(behind.commit_count().await, source.commit_count().await);
+    for sequence in 0..source.commit_count().await {
+        assert_eq!(behind.commit_digest(sequence).await, source.commit_digest(sequence).await);
+    }
+}
+
+#[tokio::test]
+async fn propose_needs_quorum_of_parents() {
+    let vset = make_validator_set(4);
+    let mut c = with_all_keys(ConsensusHandle::new(vset));
+
+    c.propose_block(vec![], 1).await.unwrap();
+    c.advance_round().await;
+
+    // nothing certified in round 0
+    let err = c.propose_block(vec![], 1).await.unwrap_err();
+    assert!(err.contains("Not enough certified parents"));
+}
+
+/*
+    with the threshold coin the wave 0 leader is unknown until f+1 round 2
+    blocks carrying coin shares show up
+*/
+#[tokio::test]
+async fn coin_reveals_leader_in_third_round() {
+    let vset = make_validator_set(4);
+    let coins = deal_coin(&vset, 42);
+    let mut c = with_all_keys(ConsensusHandle::with_coin(vset, Coin::Threshold(coins[&1].clone())));
+
+    let round0 = full_round(&mut c, 1..=4, 4).await;
+    c.advance_round().await;
+    full_round(&mut c, 1..=4, 4).await;
+    c.advance_round().await;
+
+    // our own round 2 block has our share - not enough on its own
+    let own = c.propose_block(vec![], 1).await.unwrap();
+    assert_eq!(own.coin_share.map(|s| s.wave), Some(0));
+    for v in 1..=3 {
+        c.vote_block(&own.hash, v).await.unwrap();
+    }
+    assert!(c.commit_blocks().await.is_empty(), "coin not revealed yet");
+
+    // second share comes in from author 2 - only counts once its block is certified
+    let parents = own.parents.clone();
+    let block = Block::new(vec![], parents, 2, 2).with_coin_share(coins[&2].share(0));
+    c.accept_block(block.clone()).await.unwrap();
+    assert!(c.commit_blocks().await.is_empty(), "uncertified share counted");
+    for v in 1..=3 {
+        c.vote_block(&block.hash, v).await.unwrap();
+    }
+
+    let committed = c.commit_blocks().await;
+    assert_eq!(committed.len(), 1);
+    assert!(round0.iter().any(|b| b.hash == committed[0].leader));
+
+    // shares that don't check out are refused
+    let mut forged = coins[&3].share(0);
+    forged.value ^= 1;
+    let bad = Block::new(vec![], own.parents.clone(), 3, 2).with_coin_share(forged);
+    assert!(c.accept_block(bad).await.is_err());
+}
+
+/*
+    wave 0 leader only gets 1 vote (needs f+1 = 2) so it is skipped at round 2.
+    The wave 1 leader has a path to it, so it must be ordered before wave 1
+*/
+#[tokio::test]
+async fn skipped_leader_committed_recursively() {
+    let vset = make_validator_set(4);
+    let mut c = with_all_keys(ConsensusHandle::new(vset));
+
+    let round0 = full_round(&mut c, 1..=4, 4).await;
+    let leader0 = round0[0].hash;
+    let others: Vec<_> = round0[1..].iter().map(|b| b.hash).collect();
+
+    // round 1 - only author 2 points at the leader
+    c.advance_round().await;
+    let mut round1 = vec![Block::new(vec![], round0.iter().map(|b| b.hash).collect(), 2, 1)];
+    for author in [1, 3, 4] {
+        round1.push(Block::new(vec![], others.clone(), author, 1));
+    }
+    for block in &round1 {
+        c.accept_block(block.clone()).await.unwrap();
+        for v in 1..=4 {
+            c.vote_block(&block.hash, v).await.unwrap();
+        }
+    }
+
+    c.advance_round().await;
+    assert!(c.commit_blocks().await.is_empty(), "wave 0 leader lacks support");
+    let round2 = full_round(&mut c, 1..=4, 4).await;
+    c.advance_round().await;
+    full_round(&mut c, 1..=4, 4).await;
+
+    // round 4 - wave 1 leader is author 3 of round 2
+    c.advance_round().await;
+    let leader1 = round2[2].hash;
+    let committed = c.commit_blocks().await;
+
+    assert_eq!(committed.len(), 2);
+    assert_eq!(committed[0].leader, leader0, "skipped leader goes first");
+    assert_eq!(committed[1].leader, leader1);
+    assert_eq!((committed[0].sequence, committed[1].sequence), (0, 1));
+
+    // a light client can't count f+1 votes for the skipped leader - the proof goes through the wave 1 leader
+    let vset = make_validator_set(4);
+    let tusk = ConsensusMode::default();
+    let tx = &round0[0].txs[0];
+    let proof = c.commit_proof(tx.id).await.expect("skipped leader's tx is committed");
+    assert_eq!(proof.sequence, 0);
+    let anchor = proof.anchor.as_ref().expect("proven through the wave 1 leader");
+    assert_eq!(anchor.block.hash, leader1);
+    assert_eq!(anchor.path.len(), 1, "wave 1 leader -> author 2 in round 1 -> leader");
+    assert_eq!(proof.verify(&vset, &tusk, None).unwrap().id, tx.id);
+
+    // the skipped leader's own votes don't do it
+    let mut no_anchor = proof.clone();
+    no_anchor.anchor = None;
+    no_anchor.support = Vec::new();
+    for block in &round1[..1] {
+        no_anchor.support.push(c.certified_block(&block.hash).await.unwrap());
+    }
+    assert!(no_anchor.verify(&vset, &tusk, None).is_err());
+
+    // and the path has to stick to strong parents that reach it
+    let mut detour = proof.clone();
+    detour.anchor.as_mut().unwrap().path[0] = round1[1].clone();
+    assert!(detour.verify(&vset, &tusk, None).is_err());
+}
+
+/*
+    two nodes that see the same blocks have to output exactly the same
+    sub-dags: same blocks in the same order, same txs
+*/
+#[tokio::test]
+async fn commits_are_deterministic() {
+    let mut a = with_all_keys(ConsensusHandle::new(make_validator_set(4)));
+    let mut b = with_all_keys(ConsensusHandle::new(make_validator_set(4)));
+
+    for round in 0..=3 {
+        if round > 0 {
+            a.advance_round().await;
+            b.advance_round().await;
+        }
+        for block in full_round(&mut a, 1..=4, 4).await {
+            b.accept_block(block.clone()).await.unwrap();
+            for v in 1..=4 {
+                b.vote_block(&block.hash, v).await.unwrap();
+            }
+        }
+    }
+    a.advance_round().await;
+    b.advance_round().await;
+
+    let from_a = a.commit_blocks().await;
+    let from_b = b.commit_blocks().await;
+    assert!(!from_a.is_empty());
+    assert_eq!(from_a.len(), from_b.len());
+
+    for (x, y) in from_a.iter().zip(&from_b) {
+        assert_eq!(x.sequence, y.sequence);
+        assert_eq!(x.leader, y.leader);
+        assert_eq!(x.block_hashes(), y.block_hashes());
+        let tx_ids = |d: &CommittedSubDag| d.transactions.iter().map(|t| t.id).collect::<Vec<_>>();
+        assert_eq!(tx_ids(x), tx_ids(y));
+
+        // sorted by round then author, leader last
+        let keys: Vec<_> = x.blocks.iter().map(|b| (b.round, b.author)).collect();
+        assert!(keys.windows(2).all(|w| w[0] <= w[1]));
+        assert_eq!(x.blocks.last().unwrap().hash, x.leader);
+    }
+}
+
+/*
+    each commit's digest chains the one before, so comparing the latest
+    digest compares the whole history
+*/
+#[tokio::test]
+async fn commit_log_is_hash_chained() {
+    let mut a = with_all_keys(ConsensusHandle::new(make_validator_set(4)));
+    let mut b = with_all_keys(ConsensusHandle::new(make_validator_set(4)));
+    // same blocks but the naive rule orders them differently
+    let mut c = with_all_keys(ConsensusHandle::with_mode(make_validator_set(4), Coin::Deterministic, ConsensusMode::RoundRobin));
+
+    for round in 0..=5 {
+        if round > 0 {
+            for node in [&a, &b, &c] {
+                node.advance_round().await;
+            }
+        }
+        for block in full_round(&mut a, 1..=4, 4).await {
+            for other in [&mut b, &mut c] {
+                other.accept_block(block.clone()).await.unwrap();
+                for v in 1..=4 {
+                    other.vote_block(&block.hash, v).await.unwrap();
+                }
+            }
+        }
+        for node in [&mut a, &mut b, &mut c] {
+            node.commit_blocks().await;
+        }
+    }
+
+    let mut previous = [0u8; 32];
+    let mut sequence = 0;
+    let mut from_a = a.subscribe(0);
+    while let Some(digest) = a.commit_digest(sequence).await {
+        let sub_dag = from_a.next().await.unwrap();
+        assert!(sub_dag.verify_digest());
+        assert_eq!(sub_dag.previous_digest, previous);
+        assert_eq!(b.commit_digest(sequence).await, Some(digest), "same history same digest");
+        previous = digest;
+        sequence += 1;
+    }
+    assert!(sequence >= 2);
+
+    // both commit the round 0 leader first, then round robin goes its own way
+    assert_eq!(c.commit_digest(0).await, a.commit_digest(0).await);
+    assert_ne!(c.commit_digest(1).await, a.commit_digest(1).await);
+}
+
+fn bullshark(fallback: bool) -> ConsensusMode {
+    ConsensusMode::Bullshark(BullsharkConfig { fallback, ..Default::default() })
+}
+
+/*
+    bullshark commits the round 0 anchor as soon as round 1 has f+1 votes
+    for it - tusk would still be waiting for round 2
+*/
+#[tokio::test]
+async fn bullshark_commits_with_next_round_votes() {
+    let mut c = with_all_keys(ConsensusHandle::with_mode(make_validator_set(4), Coin::Deterministic, bullshark(false)));
+
+    let round0 = full_round(&mut c, 1..=4, 4).await;
+    c.advance_round().await;
+    full_round(&mut c, 1..=4, 4).await;
+
+    let committed = c.commit_blocks().await;
+    assert_eq!(committed.len(), 1);
+    assert_eq!(committed[0].leader, round0[0].hash);
+
+    // round 2 anchor (slot 1 - author 2) commits off round 3 votes
+    c.advance_round().await;
+    let round2 = full_round(&mut c, 1..=4, 4).await;
+    assert!(c.commit_blocks().await.is_empty());
+    c.advance_round().await;
+    full_round(&mut c, 1..=4, 4).await;
+
+    let committed = c.commit_blocks().await;
+    assert_eq!(committed.len(), 1);
+    assert_eq!(committed[0].leader, round2[1].hash);
+    assert_eq!(committed[0].sequence, 1);
+}
+
+#[tokio::test]
+async fn bullshark_waits_for_leader() {
+    let mut c = with_all_keys(ConsensusHandle::with_mode(make_validator_set(4), Coin::Deterministic, bullshark(false)));
+
+    // round 0 - everyone but the leader (author 1)
+    let mut round0 = full_round(&mut c, 2..=4, 4).await;
+    assert!(!c.leader_ready().await, "no anchor yet");
+    round0.extend(full_round(&mut c, 1..=1, 4).await);
+    assert!(c.leader_ready().await);
+
+    // round 1 - need f+1 votes for the anchor before moving on
+    c.advance_round().await;
+    assert!(!c.leader_ready().await, "no votes yet");
+    full_round(&mut c, 1..=2, 4).await;
+    assert!(c.leader_ready().await);
+}
+
+/*
+    the round 0 steady leader (1) is down, so the round 1 blocks can't point
+    at it - that's 2f+1 fallback votes and the coin leader takes the slot once
+    the round 2 shares reveal it
+*/
+#[tokio::test]
+async fn bullshark_fallback_uses_coin() {
+    let vset = make_validator_set(4);
+    let coins = deal_coin(&vset, 9);
+    let mut c = with_all_keys(ConsensusHandle::with_mode(vset.clone(), Coin::Threshold(coins[&2].clone()), bullshark(true)));
+
+    let round0 = full_round(&mut c, 2..=4, 4).await;
+    assert!(!c.leader_ready().await, "waiting on the steady anchor");
+    c.advance_round().await;
+    full_round(&mut c, 2..=4, 4).await;
+    assert!(c.commit_blocks().await.is_empty(), "coin not revealed");
+
+    c.advance_round().await;
+    let own = c.propose_block(vec![], 2).await.unwrap();
+    let block = Block::new(vec![], own.parents.clone(), 3, 2).with_coin_share(coins[&3].share(0));
+    c.accept_block(block.clone()).await.unwrap();
+    for hash in [own.hash, block.hash] {
+        for v in 1..=3 {
+            c.vote_block(&hash, v).await.unwrap();
+        }
+    }
+
+    let committed = c.commit_blocks().await;
+    assert_eq!(committed.len(), 1);
+    let leader = round0.iter().find(|b| b.hash == committed[0].leader).unwrap();
+
+    // a light client redoes the coin from the shares in the proof
+    let proof = c.commit_proof(leader.txs[0].id).await.unwrap();
+    let keys = coins[&1].public_keys();
+    assert_eq!(proof.coin_shares.len(), 2);
+    assert_eq!(proof.fallback_votes.len(), 3);
+    assert!(proof.verify(&vset, &bullshark(true), Some(keys)).is_ok());
+
+    // the coin leader only needs f+1 support, but all 2f+1 fallback votes
+    let mut two_votes = proof.clone();
+    two_votes.support.truncate(2);
+    assert!(two_votes.verify(&vset, &bullshark(true), Some(keys)).is_ok());
+    let mut two_fallback = proof.clone();
+    two_fallback.fallback_votes.truncate(2);
+    assert!(two_fallback.verify(&vset, &bullshark(true), Some(keys)).is_err());
+    // hiding a parent could hide a vote for the steady leader
+    let mut hidden = proof.clone();
+    hidden.fallback_votes[0].parents.pop();
+    assert!(hidden.verify(&vset, &bullshark(true), Some(keys)).is_err());
+
+    let mut one_share = proof.clone();
+    one_share.coin_shares.truncate(1);
+    assert!(one_share.verify(&vset, &bullshark(true), Some(keys)).is_err(), "coin not revealed by one share");
+    // without the fallback only the steady leader counts
+    assert!(proof.verify(&vset, &bullshark(false), Some(keys)).is_err());
+}
+
+// everyone showed up for the steady anchor - 2f+1 votes and the coin never comes into it
+#[tokio::test]
+async fn bullshark_steady_anchor_needs_quorum_with_fallback() {
+    let vset = make_validator_set(4);
+    let mut c = with_all_keys(ConsensusHandle::with_mode(vset.clone(), Coin::Deterministic, bullshark(true)));
+
+    let round0 = full_round(&mut c, 1..=4, 4).await;
+    c.advance_round().await;
+    full_round(&mut c, 1..=2, 4).await;
+    assert!(!c.leader_ready().await, "f+1 votes aren't enough to commit with the fallback on");
+    assert!(c.commit_blocks().await.is_empty());
+
+    full_round(&mut c, 3..=3, 4).await;
+    assert!(c.leader_ready().await);
+    let committed = c.commit_blocks().await;
+    assert_eq!(committed.len(), 1);
+    assert_eq!(committed[0].leader, round0[0].hash);
+
+    // the deterministic coin picks the same leader, but without fallback votes the proof needs all three
+    let proof = c.commit_proof(round0[0].txs[0].id).await.unwrap();
+    assert!(proof.fallback_votes.is_empty());
+    assert!(proof.verify(&vset, &bullshark(true), None).is_ok());
+    let mut two_votes = proof.clone();
+    two_votes.support.truncate(2);
+    assert!(two_votes.verify(&vset, &bullshark(true), None).is_err());
+}
+
+/*
+    validator 1 never starts, so every 4th anchor slot has a steady leader
+    that isn't there. The others time out on it and the coin commits those
+    slots - without the fallback they just get skipped
+*/
+#[tokio::test]
+async fn bullshark_coin_commits_when_steady_leader_is_down() {
+    async fn slots_led_by_one(fallback: bool) -> usize {
+        let vset = make_validator_set(4);
+        let coins = deal_coin(&vset, 5);
+        let keys = signing_keys(&vset);
+        let engine = BullsharkConfig {
+            leader_timeout: std::time::Duration::from_millis(100),
+            fallback,
+            // keep 1 in the schedule
+            reputation: ReputationConfig { commits_per_update: 0, bad_leaders: 0 },
+        };
+        let config = SimulationConfig { latency_ms: (5, 20), packet_loss_rate: 0.0, node_churn: 0.0, ..Default::default() };
+        let mut cluster = Cluster::new(Simulator::new(config), std::sync::Arc::new(move |id| {
+            ConsensusHandle::with_mode(vset.clone(), Coin::Threshold(coins[&id].clone()), ConsensusMode::Bullshark(engine.clone()))
+                .with_keys([keys[&id].clone()])
+        }));
+        cluster.start(2..=4).await;
+        cluster.run(std::time::Duration::from_secs(2)).await;
+        assert_logs_agree(&cluster, 2..=4, "coin slots").await;
+
+        let node = cluster.consensus(2).unwrap();
+        let count = node.commit_count().await as usize;
+        let commits: Vec<CommittedSubDag> = node.subscribe(0).take(count).collect().await;
+        assert!(commits.len() > 5, "only {} commits", commits.len());
+        // slot = round / 2 and the schedule is 1..=4
+        commits.iter().filter(|sub_dag| sub_dag.round % 8 == 0).count()
+    }
+
+    assert!(slots_led_by_one(true).await > 0);
+    assert_eq!(slots_led_by_one(false).await, 0);
+}
+
+// who each engine would elect - what delay-leader aims at. Tusk and bullshark only have leaders on even rounds
+#[tokio::test]
+async fn engines_name_their_leaders() {
+    let handle = |mode| with_all_keys(ConsensusHandle::with_mode(make_validator_set(4), Coin::Deterministic, mode));
+
+    let tusk = handle(ConsensusMode::default());
+    assert_eq!(tusk.leaders(2).await, vec![3]);
+    assert!(tusk.leaders(3).await.is_empty());
+
+    // steady anchor of slot 1, then the coin's pick for the same round
+    assert_eq!(handle(bullshark(true)).leaders(2).await, vec![2, 3]);
+    assert_eq!(handle(bullshark(false)).leaders(2).await, vec![2]);
+    assert!(handle(bullshark(true)).leaders(3).await.is_empty());
+
+    assert_eq!(handle(ConsensusMode::RoundRobin).leaders(3).await, vec![choose_leader(3, 4)]);
+}
+
+/*
+    same workload through every engine: round 0 is certified but nobody
+    builds round 1, so only the naive round robin rule commits anything
+*/
+#[tokio::test]
+async fn engines_compared_on_same_workload() {
+    let mut source = with_all_keys(ConsensusHandle::with_mode(make_validator_set(4), Coin::Deterministic, ConsensusMode::RoundRobin));
+    let round0 = full_round(&mut source, 1..=4, 4).await;
+
+    for mode in ["tusk", "bullshark", "round-robin"] {
+        let mut c = with_all_keys(ConsensusHandle::with_mode(make_validator_set(4), Coin::Deterministic, mode.parse().unwrap()));
+        assert_eq!(c.engine_name(), mode);
+        assert_eq!(mode.parse::<ConsensusMode>().unwrap().to_string(), mode);
+
+        for block in &round0 {
+            c.accept_block(block.clone()).await.unwrap();
+            for v in 1..=4 {
+                c.vote_block(&block.hash, v).await.unwrap();
+            }
+        }
+        c.advance_round().await;
+        c.advance_round().await;
+
+        let committed = c.commit_blocks().await;
+        if mode == "round-robin" {
+            assert_eq!(committed.len(), 1);
+            assert_eq!(committed[0].leader, round0[0].hash);
+        } else {
+            assert!(committed.is_empty(), "{} needs votes in round 1", mode);
+        }
+    }
+
+    // same engine, the name just says the fallback is on
+    let fallback: ConsensusMode = "bullshark-fallback".parse().unwrap();
+    assert!(matches!(&fallback, ConsensusMode::Bullshark(config) if config.fallback));
+    assert_eq!(fallback.to_string(), "bullshark-fallback");
+    assert!("paxos".parse::<ConsensusMode>().is_err());
+}
+
+// every node the simulator starts runs the engine from the config
+#[tokio::test]
+async fn simulator_runs_configured_engine() {
+    let config = SimulationConfig {
+        mode: ConsensusMode::RoundRobin,
+        latency_ms: (5, 20),
+        packet_loss_rate: 0.0,
+        node_churn: 0.0,
+        ..Default::default()
+    };
+    let mut cluster = start_cluster(Simulator::new(config), 4).await;
+    cluster.run(std::time::Duration::from_millis(1000)).await;
+
+    for id in 1..=4 {
+        assert_eq!(cluster.consensus(id).unwrap().engine_name(), "round-robin");
+    }
+    assert!(assert_logs_agree(&cluster, 1..=4, "round robin").await > 0);
+}
+
+/*
+    validator 4 is crashed. Round robin would make it the slot 3 (round 6)
+    anchor, but after 2 commits the reputation schedule drops it and
+    validator 1 takes the slot instead
+*/
+#[tokio::test]
+async fn reputation_skips_crashed_leader() {
+    let reputation = ReputationConfig { commits_per_update: 2, bad_leaders: 1 };
+    let mode = ConsensusMode::Bullshark(BullsharkConfig { fallback: false, reputation, ..Default::default() });
+    let mut c = with_all_keys(ConsensusHandle::with_mode(make_validator_set(4), Coin::Deterministic, mode));
+
+    let mut committed = Vec::new();
+    let mut round6 = Vec::new();
+    for round in 0..=7 {
+        if round > 0 {
+            c.advance_round().await;
+        }
+        let blocks = full_round(&mut c, 1..=3, 4).await;
+        if round == 6 {
+            round6 = blocks;
+        }
+        committed.extend(c.commit_blocks().await);
+    }
+
+    let leaders: Vec<_> = committed.iter().map(|d| d.round).collect();
+    assert_eq!(leaders, vec![0, 2, 4, 6]);
+    assert_eq!(committed[3].leader, round6[0].hash, "slot 3 goes to validator 1");
+}
+
+// same for tusk: validator 1 is down and only leaves the rotation if the schedule is on
+#[tokio::test]
+async fn tusk_reputation_is_configurable() {
+    async fn leader_rounds(commits_per_update: u64) -> Vec<u32> {
+        let mode = ConsensusMode::Tusk(ReputationConfig { commits_per_update, bad_leaders: 1 });
+        let mut c = with_all_keys(ConsensusHandle::with_mode(make_validator_set(4), Coin::Deterministic, mode));
+
+        let mut committed = Vec::new();
+        for round in 0..=12 {
+            if round > 0 {
+                c.advance_round().await;
+            }
+            full_round(&mut c, 2..=4, 4).await;
+            committed.extend(c.commit_blocks().await);
+        }
+        committed.iter().map(|d| d.round).collect()
+    }
+
+    // round 8 is slot 0 of [1, 2, 3, 4] but slot 2 of [2, 3, 4]
+    assert_eq!(leader_rounds(2).await, vec![2, 6, 8, 10]);
+    assert_eq!(leader_rounds(0).await, vec![2, 6, 10]);
+}
+
+/*
+    two subscribers: one replays from the start, one only wants sequence 1
+    onwards. Both see commits in order, including ones made after subscribing
+*/
+#[tokio::test]
+async fn subscribers_get_ordered_commits() {
+    let mode = ConsensusMode::Bullshark(BullsharkConfig { fallback: false, ..Default::default() });
+    let mut c = with_all_keys(ConsensusHandle::with_mode(make_validator_set(4), Coin::Deterministic, mode));
+
+    let mut from_start = c.subscribe(0);
+    for round in 0..=3 {
+        if round > 0 {
+            c.advance_round().await;
+        }
+        full_round(&mut c, 1..=4, 4).await;
+        c.commit_blocks().await;
+    }
+    let mut late = c.subscribe(1);
+
+    let first = from_start.next().await.unwrap();
+    let second = from_start.next().await.unwrap();
+    assert_eq!((first.sequence, first.round), (0, 0));
+    assert_eq!((second.sequence, second.round), (1, 2));
+    assert!(!second.transactions.is_empty());
+    assert_eq!(late.next().await.unwrap().leader, second.leader);
+
+    // commit after subscribing - both wake up
+    c.advance_round().await;
+    full_round(&mut c, 1..=4, 4).await;
+    c.advance_round().await;
+    full_round(&mut c, 1..=4, 4).await;
+    c.commit_blocks().await;
+
+    assert_eq!(from_start.next().await.unwrap().sequence, 2);
+    assert_eq!(late.next().await.unwrap().sequence, 2);
+
+    // stream ends once the handle is gone
+    drop(c);
+    assert!(late.next().await.is_none());
+}
+
+// author collects votes into a certificate, everyone else only trusts it after checking it
+#[tokio::test]
+async fn author_forms_certificate_from_votes() {
+    let vset = make_validator_set(4);
+    let keys = signing_keys(&vset);
+    let mut author = with_all_keys(ConsensusHandle::new(vset.clone()));
+    let mut other = with_all_keys(ConsensusHandle::new(vset));
+
+    let block = author.propose_block(vec![Transaction::new("tx".to_string())], 1).await.unwrap();
+    other.accept_block(block.clone()).await.unwrap();
+
+    // votes come back to the author, the cert shows up exactly at 2f+1
+    let vote = other.create_vote(&block.hash, 2).await.unwrap();
+    assert!(author.add_vote(vote.clone()).await.unwrap().is_none());
+    assert!(author.add_vote(vote).await.unwrap().is_none(), "duplicate vote counted");
+    assert!(author.add_vote(Vote::new(block.hash, 0, &keys[&3])).await.unwrap().is_none());
+
+    let mut forged = Vote::new(block.hash, 0, &keys[&4]);
+    forged.signature = [4u8; 16];
+    assert!(author.add_vote(forged).await.is_err());
+    // 3's key can't sign for 4
+    let mut stolen = Vote::new(block.hash, 0, &keys[&3]);
+    stolen.voter = 4;
+    assert!(author.add_vote(stolen).await.is_err());
+    // a handle only signs for the keys it holds
+    let mut own_key = ConsensusHandle::new(make_validator_set(4)).with_keys([keys[&2].clone()]);
+    own_key.accept_block(block.clone()).await.unwrap();
+    assert_eq!(own_key.create_vote(&block.hash, 3).await.unwrap_err(), VoteError::NoSigningKey(3));
+
+    let cert = author.add_vote(Vote::new(block.hash, 0, &keys[&4])).await.unwrap().expect("cert at quorum");
+    assert!(author.add_vote(Vote::new(block.hash, 0, &keys[&1])).await.unwrap().is_none(), "cert handed out twice");
+
+    // tampered certificates are rejected
+    let mut bad_sig = cert.clone();
+    bad_sig.signatures[0].1 = [0u8; 16];
+    assert!(other.accept_certificate(bad_sig).await.is_err());
+
+    let mut duplicated = cert.clone();
+    duplicated.signatures[1] = duplicated.signatures[0];
+    assert!(other.accept_certificate(duplicated).await.is_err());
+
+    let mut short = cert.clone();
+    short.signatures.pop();
+    assert!(other.accept_certificate(short).await.is_err());
+
+    assert!(!other.cert_is_valid(&block.hash).await);
+    other.accept_certificate(cert).await.unwrap();
+    assert!(other.cert_is_valid(&block.hash).await);
+}
+
+// the round isn't under the signatures - a certificate that shows up before its header still gets checked against it
+#[tokio::test]
+async fn certificate_round_checked_when_header_comes_later() {
+    let vset = make_validator_set(4);
+    let mut author = with_all_keys(ConsensusHandle::new(vset.clone()));
+    let mut other = with_all_keys(ConsensusHandle::new(vset));
+
+    let block = author.propose_block(vec![], 1).await.unwrap();
+    let mut cert = None;
+    for v in 1..=3 {
+        cert = author.vote_block(&block.hash, v).await.unwrap().or(cert);
+    }
+    let cert = cert.expect("vote_block hands back the certificate");
+
+    let mut moved = cert.clone();
+    moved.round = 5;
+    other.accept_certificate(moved).await.unwrap();
+    assert!(other.accept_block(block.clone()).await.is_err());
+    assert!(!other.dag_contains(&block.hash).await);
+
+    // the real one still gets it in
+    other.accept_certificate(cert).await.unwrap();
+    assert!(other.dag_contains(&block.hash).await);
+}
+
+// nothing gets parked in pending_headers unless it hashes right and is within reach
+#[tokio::test]
+async fn tampered_and_far_ahead_headers_are_refused() {
+    let mut c = with_all_keys(ConsensusHandle::new(make_validator_set(4)));
+
+    let mut tampered = Block::new(vec![], vec![], 2, 0);
+    tampered.txs.push(Transaction::new("slipped in".to_string()));
+    assert!(c.accept_block(tampered.clone()).await.is_err());
+    assert!(!c.is_pending(&tampered.hash).await);
+
+    let far = Block::new(vec![], vec![[1; 32]], 2, WEAK_LINK_DEPTH + 1);
+    assert!(c.accept_block(far.clone()).await.is_err());
+    assert!(!c.is_pending(&far.hash).await);
+    let edge = Block::new(vec![], vec![[1; 32]], 2, WEAK_LINK_DEPTH);
+    c.accept_block(edge.clone()).await.unwrap();
+    assert!(c.is_pending(&edge.hash).await);
+}
+
+// headers sit outside the dag until they're certified - proposals never build on them
+#[tokio::test]
+async fn only_certified_headers_enter_dag() {
+    let mut c = with_all_keys(ConsensusHandle::new(make_validator_set(4)));
+    let mut other = with_all_keys(ConsensusHandle::new(make_validator_set(4)));
+
+    let round0 = full_round(&mut c, 1..=3, 4).await;
+    let straggler = c.propose_block(vec![], 4).await.unwrap();
+    c.vote_block(&straggler.hash, 4).await.unwrap();
+    assert!(c.is_pending(&straggler.hash).await);
+    assert!(!c.dag_contains(&straggler.hash).await);
+    assert!(round0.iter().all(|b| !b.parents.contains(&straggler.hash)));
+
+    c.advance_round().await;
+    let next = c.propose_block(vec![], 1).await.unwrap();
+    assert_eq!(next.parents.len(), 3);
+    assert!(!next.parents.contains(&straggler.hash), "uncertified parent");
+
+    // the last votes come in - promoted the moment the cert forms
+    c.vote_block(&straggler.hash, 1).await.unwrap();
+    let keys = signing_keys(c.validator_set());
+    let cert = c.add_vote(Vote::new(straggler.hash, 0, &keys[&2])).await.unwrap().expect("cert at quorum");
+    assert!(c.dag_contains(&straggler.hash).await);
+    assert!(!c.is_pending(&straggler.hash).await);
+
+    // certificate gets there before the header - promoted as soon as the header arrives
+    other.accept_certificate(cert).await.unwrap();
+    assert!(!other.dag_contains(&straggler.hash).await);
+    other.accept_block(straggler.clone()).await.unwrap();
+    assert!(other.dag_contains(&straggler.hash).await);
+}
+
+// a byzantine author can't get two blocks signed for the same slot, or blocks that skip rounds
+#[tokio::test]
+async fn voters_refuse_unsafe_blocks() {
+    let mut c = with_all_keys(ConsensusHandle::new(make_validator_set(4)));
+    let round0 = full_round(&mut c, 1..=4, 4).await;
+    let parents: Vec<_> = round0.iter().map(|b| b.hash).collect();
+
+    // equivocation: two round 1 blocks from author 1
+    let first = Block::new(vec![Transaction::new("a".to_string())], parents.clone(), 1, 1);
+    let second = Block::new(vec![Transaction::new("b".to_string())], parents.clone(), 1, 1);
+    c.accept_block(first.clone()).await.unwrap();
+    c.accept_block(second.clone()).await.unwrap();
+    assert!(c.create_vote(&first.hash, 2).await.is_ok());
+    assert!(c.create_vote(&first.hash, 2).await.is_ok(), "re-sending the same vote is fine");
+    assert_eq!(c.create_vote(&second.hash, 2).await.unwrap_err(), VoteError::Equivocation { author: 1, round: 1 });
+    // another voter hasn't signed anything for the slot yet
+    assert!(c.create_vote(&second.hash, 3).await.is_ok());
+
+    // round 2 block pointing straight at round 0
+    let skipping = Block::new(vec![], parents.clone(), 2, 2);
+    c.accept_block(skipping.clone()).await.unwrap();
+    assert_eq!(c.create_vote(&skipping.hash, 3).await.unwrap_err(), VoteError::BadParentRound { round: 2, parent_round: 0 });
+
+    let orphan = Block::new(vec![], vec![], 3, 1);
+    c.accept_block(orphan.clone()).await.unwrap();
+    assert_eq!(c.create_vote(&orphan.hash, 1).await.unwrap_err(), VoteError::NoParents { round: 1 });
+
+    // parent we haven't seen certified - retry later
+    let unknown = Block::new(vec![], vec![[9u8; 32]], 4, 1);
+    c.accept_block(unknown.clone()).await.unwrap();
+    assert_eq!(c.create_vote(&unknown.hash, 1).await.unwrap_err(), VoteError::MissingParents(vec![[9u8; 32]]));
+
+    assert_eq!(c.create_vote(&first.hash, 5).await.unwrap_err(), VoteError::UnknownVoter(5));
+    assert_eq!(c.create_vote(&[7u8; 32], 1).await.unwrap_err(), VoteError::UnknownBlock);
+
+    // past the weak link depth our votes are forgotten - so nothing that old gets signed anymore
+    for _ in 0..=WEAK_LINK_DEPTH {
+        c.advance_round().await;
+    }
+    c.commit_blocks().await;
+    assert_eq!(c.create_vote(&round0[0].hash, 1).await.unwrap_err(), VoteError::TooOld { round: 0 });
+}
+
+// light client: checks a tx is final with nothing but the validator set
+#[tokio::test]
+async fn commit_proof_checks_out_without_a_dag() {
+    let vset = make_validator_set(4);
+    let mut c = with_all_keys(ConsensusHandle::new(vset.clone()));
+
+    let mut rounds = Vec::new();
+    for round in 0..=3 {
+        if round > 0 {
+            c.advance_round().await;
+        }
+        rounds.push(full_round(&mut c, 1..=4, 4).await);
+    }
+    c.advance_round().await;
+    let committed = c.commit_blocks().await;
+    assert_eq!(committed.len(), 2);
+
+    // tx from a round 0 block that isn't the leader - the wave 1 leader picks it up
+    let tx = &rounds[0][2].txs[0];
+    assert!(committed[1].transactions.iter().any(|t| t.id == tx.id));
+    let proof = c.commit_proof(tx.id).await.expect("tx is committed");
+    let tusk = ConsensusMode::default();
+    assert_eq!(proof.sequence, 1);
+    assert_eq!(Some(proof.digest), c.commit_digest(1).await);
+    assert_eq!(proof.verify(&vset, &tusk, None).unwrap().id, tx.id);
+    assert_eq!(proof.path.len(), 2, "leader -> round 1 -> round 0");
+
+    // leader's own tx needs no path
+    let leader_tx = &rounds[2][2].txs[0];
+    let own = c.commit_proof(leader_tx.id).await.unwrap();
+    assert!(own.path.is_empty());
+    assert!(own.verify(&vset, &tusk, None).is_ok());
+
+    let mut wrong_tx = proof.clone();
+    wrong_tx.inclusion.tx = Transaction::new("not mine".to_string());
+    assert!(wrong_tx.verify(&vset, &tusk, None).is_err());
+
+    let mut no_votes = proof.clone();
+    no_votes.support.truncate(1);
+    assert!(no_votes.verify(&vset, &tusk, None).is_err());
+
+    // f+1 votes commit a tusk leader
+    let mut two_votes = own.clone();
+    two_votes.support.truncate(2);
+    assert!(two_votes.verify(&vset, &tusk, None).is_ok());
+
+    let mut wrong_slot = own.clone();
+    wrong_slot.slot += 1;
+    assert!(wrong_slot.verify(&vset, &tusk, None).is_err());
+
+    // turned down, not divided by
+    let mut no_schedule = own.clone();
+    no_schedule.schedule.clear();
+    assert!(no_schedule.verify(&vset, &tusk, None).is_err());
+
+    let mut forged = proof.clone();
+    forged.path[1].txs.push(Transaction::new("extra".to_string()));
+    assert!(forged.verify(&vset, &tusk, None).is_err());
+
+    // proof is for commit 1 - claiming another sequence breaks the digest
+    let mut moved = proof.clone();
+    moved.sequence = 0;
+    assert!(moved.verify(&vset, &tusk, None).is_err());
+
+    // someone else's validator set doesn't accept these signatures
+    assert!(proof.verify(&make_validator_set(7), &tusk, None).is_err());
+
+    /*
+        round 2 block from 1 is certified and every round 3 block points at it,
+        so it passes the vote count - but node 3 led round 2
+     */
+    let impostor = &rounds[2][0];
+    let (_, impostor_cert) = c.certified_block(&impostor.hash).await.unwrap();
+    let mut support = Vec::new();
+    for block in &rounds[3] {
+        support.push(c.certified_block(&block.hash).await.unwrap());
+    }
+    let mut not_leader = own.clone();
+    not_leader.leader = impostor.clone();
+    not_leader.leader_certificate = impostor_cert;
+    not_leader.support = support;
+    not_leader.inclusion.tx = impostor.txs[0].clone();
+    let err = not_leader.verify(&vset, &tusk, None).unwrap_err();
+    assert!(err.contains("not the leader"), "{}", err);
+
+    // a schedule with 3 and 4 dropped would make 1 the leader - more than f dropped though
+    let mut rigged = not_leader.clone();
+    rigged.schedule = vec![1, 2];
+    assert!(rigged.verify(&vset, &tusk, None).is_err());
+
+    // not committed yet
+    let later = c.propose_block(vec![Transaction::new("pending".to_string())], 1).await.unwrap();
+    assert!(c.commit_proof(later.txs[0].id).await.is_none());
+}
+
+// one round behind is normal - only jump when a quorum is further ahead than that
+#[tokio::test]
+async fn catch_up_skips_only_when_more_than_a_round_behind() {
+    let mut ahead = with_all_keys(ConsensusHandle::new(make_validator_set(4)));
+    let mut behind = with_all_keys(ConsensusHandle::new(make_validator_set(4)));
+
+    let mut rounds = Vec::new();
+    for round in 0..=2 {
+        if round > 0 {
+            ahead.advance_round().await;
+        }
+        rounds.push(full_round(&mut ahead, 1..=4, 4).await);
+    }
+
+    for round in &rounds[..2] {
+        for block in round {
+            let (block, cert) = ahead.certified_block(&block.hash).await.unwrap();
+            behind.accept_block(block).await.unwrap();
+            behind.accept_certificate(cert).await.unwrap();
+        }
+    }
+    assert_eq!(behind.catch_up().await, None, "still gets to propose in round 0");
+    assert_eq!(behind.current_round().await, 0);
+
+    for block in &rounds[2] {
+        let (block, cert) = ahead.certified_block(&block.hash).await.unwrap();
+        behind.accept_block(block).await.unwrap();
+        behind.accept_certificate(cert).await.unwrap();
+    }
+    assert_eq!(behind.catch_up().await, Some(2));
+}
+
+/*
+    Whole nodes over the simulated network: rounds only move on quorums,
+    so every validator ends up with one header per round and the leaders
+    still commit
+*/
+#[tokio::test]
+async fn nodes_advance_on_quorum() {
+    let vset = make_validator_set(4);
+    let sim = Simulator::new(SimulationConfig { latency_ms: (5, 20), packet_loss_rate: 0.0, ..Default::default() });
+
+    // register everyone first - a round 0 header sent before a node exists is gone
+    let mut transports = Vec::new();
+    for id in 1..=4 {
+        transports.push((id, sim.connect(id).await));
+    }
+
+    let keys = signing_keys(&vset);
+    let mut tasks = Vec::new();
+    let mut commits = None;
+    for (id, transport) in transports {
+        let consensus = ConsensusHandle::new(vset.clone()).with_keys([keys[&id].clone()]);
+        if id == 1 {
+            commits = Some(consensus.subscribe(0));
+        }
+        let node = Node::with_consensus(id, transport, consensus);
+        tasks.push(tokio::spawn(node.run_node()));
+    }
+
+    let mut commits = commits.unwrap();
+    let mut seen = std::collections::HashSet::new();
+    for _ in 0..3 {
+        let sub_dag = tokio::time::timeout(std::time::Duration::from_secs(10), commits.next())
+            .await
+            .expect("nodes stopped committing")
+            .unwrap();
+        for block in &sub_dag.blocks {
+            assert!(seen.insert((block.round, block.author)), "two headers from {} in round {}", block.author, block.round);
+        }
+    }
+
+    for task in tasks {
+        task.abort();
+    }
+}
+
+/*
+    {1,2} | {3,4,5} for a while - neither side has 2f+1 so nothing commits
+    on either, and 5 can never reach 1 directly. Once the split heals the
+    re-sent headers get everyone going again and all five agree on the log
+*/
+#[tokio::test]
+async fn tusk_recovers_from_partition() {
+    let vset = make_validator_set(5);
+    let ms = std::time::Duration::from_millis;
+    let config = SimulationConfig {
+        latency_ms: (5, 20),
+        packet_loss_rate: 0.0,
+        faults: vec![
+            NetworkFault::partition(vec![vec![1, 2], vec![3, 4, 5]], ms(300), Some(ms(1500))),
+            NetworkFault::link_down(5, 1, ms(0), None),
+        ],
+        ..Default::default()
+    };
+    let sim = Simulator::new(config);
+
+    let mut transports = Vec::new();
+    for id in 1..=5 {
+        transports.push((id, sim.connect(id).await));
+    }
+
+    let keys = signing_keys(&vset);
+    let mut tasks = Vec::new();
+    let mut handles = Vec::new();
+    for (id, transport) in transports {
+        let consensus = ConsensusHandle::new(vset.clone()).with_keys([keys[&id].clone()]);
+        handles.push(consensus.clone());
+        tasks.push(tokio::spawn(Node::with_consensus(id, transport, consensus).run_node()));
+    }
+
+    tokio::time::sleep(ms(1500).saturating_sub(sim.elapsed())).await;
+    // longest log anyone has when the split heals
+    let mut healed_at = 0;
+    for c in &handles {
+        while c.commit_digest(healed_at).await.is_some() {
+            healed_at += 1;
+        }
+    }
+
+    // a few more commits on every node after the heal
+    let target = healed_at + 3;
+    for c in &handles {
+        let mut commits = c.subscribe(target);
+        tokio::time::timeout(std::time::Duration::from_secs(10), commits.next())
+            .await
+            .expect("no progress after the partition healed");
+    }
+
+    for sequence in 0..=target {
+        let digest = handles[0].commit_digest(sequence).await;
+        assert!(digest.is_some());
+        for c in &handles[1..] {
+            assert_eq!(c.commit_digest(sequence).await, digest, "logs split at sequence {}", sequence);
+        }
+    }
+
+    for task in tasks {
+        task.abort();
+    }
+}
+
+/*
+    One node comes back with everything it had, another with nothing. Both
+    have to get back into the current round and rebuild the same commit log
+    as everyone else - the fresh one by fetching the whole history
+*/
+#[tokio::test]
+async fn crashed_nodes_catch_up() {
+    let ms = std::time::Duration::from_millis;
+    let config = SimulationConfig {
+        latency_ms: (5, 20),
+        packet_loss_rate: 0.0,
+        node_churn: 0.0,
+        crashes: vec![
+            Crash { node: 4, at: ms(300), down_for: Some(ms(700)), restart: Restart::Persisted },
+            Crash { node: 3, at: ms(1500), down_for: Some(ms(500)), restart: Restart::Fresh },
+        ],
+        ..Default::default()
+    };
+
+    let mut cluster = start_cluster(Simulator::new(config), 4).await;
+    cluster.run(ms(3500)).await;
+
+    let report = cluster.report();
+    assert_eq!(report.recoveries.len(), 2);
+    assert!(report.still_down.is_empty());
+    for recovery in &report.recoveries {
+        assert!(recovery.caught_up(), "{}", recovery);
+    }
+    let fresh = &report.recoveries[1];
+    assert_eq!((fresh.node, fresh.round_at_restart, fresh.commits_at_restart), (3, 0, 0));
+    assert!(fresh.target_commits > 0);
+
+    // same log everywhere, the restarted ones included
+    assert_logs_agree(&cluster, 1..=4, "after restarts").await;
+}
+
+/*
+    Same seed, same run: lossy links and random crashes, twice, each on its
+    own virtual-time runtime, down to the block hashes and commit digests
+*/
+#[test]
+fn seeded_runs_replay_exactly() {
+    // (digest, hash of every block) per commit
+    type Commits = Vec<(Hash, Vec<Hash>)>;
+
+    fn simulate(seed: u64) -> (Commits, String) {
+        let config = SimulationConfig {
+            latency_ms: (5, 50),
+            packet_loss_rate: 0.1,
+            node_churn: 0.05,
+            seed,
+            virtual_time: true,
+            // session ids come from the seed too
+            reliable: Some(ReliableConfig::default()),
+            ..Default::default()
+        };
+        config.runtime().unwrap().block_on(async move {
+            let mut cluster = start_cluster(Simulator::new(config), 4).await;
+            cluster.run(std::time::Duration::from_secs(20)).await;
+
+            let consensus = cluster.consensus(1).unwrap();
+            let count = consensus.commit_count().await as usize;
+            let commits = consensus.subscribe(0).take(count)
+                .map(|sub_dag| (sub_dag.digest, sub_dag.blocks.iter().map(|b| b.hash).collect()))
+                .collect().await;
+            (commits, cluster.report().to_string())
+        })
+    }
+
+    let (commits, report) = simulate(1234);
+    assert!(commits.len() > 5, "only {} commits", commits.len());
+    assert!(report != "0 restarts\n", "no churn happened");
+    assert_eq!(simulate(1234), (commits, report));
+}
+
+// a minute of protocol time has to go by in a few real seconds - the clock jumps, nobody sleeps
+#[test]
+fn virtual_time_outruns_the_clock() {
+    let config = SimulationConfig { latency_ms: (50, 200), packet_loss_rate: 0.0, node_churn: 0.0, seed: 21, virtual_time: true, ..Default::default() };
+    let started = std::time::Instant::now();
+    let (simulated, commits) = config.runtime().unwrap().block_on(async move {
+        let sim = Simulator::new(config);
+        let mut cluster = start_cluster(sim.clone(), 4).await;
+        cluster.run(std::time::Duration::from_secs(60)).await;
+        (sim.elapsed(), cluster.consensus(1).unwrap().commit_count().await)
+    });
+
+    assert!(simulated >= std::time::Duration::from_secs(60));
+    assert!(commits > 50, "only {} commits", commits);
+    assert!(started.elapsed() < std::time::Duration::from_secs(10), "60s of virtual time took {:?}", started.elapsed());
+}
+
+// random churn counts the byzantine node against f - with 4 nodes nobody else may go down
+#[tokio::test]
+async fn churn_leaves_room_for_byzantine_nodes() {
+    async fn restarts(byzantine_nodes: Vec<(u32, Behavior)>) -> usize {
+        let config = SimulationConfig {
+            latency_ms: (5, 20),
+            packet_loss_rate: 0.0,
+            node_churn: 5.0,
+            churn_downtime: std::time::Duration::from_millis(100),
+            byzantine_nodes,
+            ..Default::default()
+        };
+        let mut cluster = start_cluster(Simulator::new(config), 4).await;
+        cluster.run(std::time::Duration::from_millis(1000)).await;
+        cluster.report().recoveries.len()
+    }
+
+    assert!(restarts(Vec::new()).await > 0);
+    assert_eq!(restarts(vec![(4, "silent".parse().unwrap())]).await, 0);
+}
+
+/*
+    the threshold coin hides a tusk leader until its round is over, so
+    delay-leader has nobody to aim at - a bullshark steady anchor is known
+    ahead and everything for it sits out the delay
+*/
+#[tokio::test(start_paused = true)]
+async fn delay_leader_holds_back_the_steady_anchor() {
+    let vset = make_validator_set(4);
+    let coins = deal_coin(&vset, 3);
+    let handle = |mode| with_all_keys(ConsensusHandle::with_mode(vset.clone(), Coin::Threshold(coins[&4].clone()), mode));
+    assert!(handle(ConsensusMode::default()).leaders(2).await.is_empty());
+    let c = handle(bullshark(false));
+    assert_eq!(c.leaders(2).await, vec![2]);
+
+    let sim = Simulator::new(SimulationConfig { latency_ms: (5, 20), packet_loss_rate: 0.0, node_churn: 0.0, ..Default::default() });
+    let mut byzantine = ByzantineTransport::new(sim.connect(4).await, 4, "delay-leader".parse().unwrap(), c);
+    let mut leader = sim.connect(2).await;
+    let mut other = sim.connect(1).await;
+
+    let block = Block::new(vec![], vec![], 4, 2);
+    for to in [1, 2] {
+        byzantine.send(NetworkMsg { from: 4, to, payload: MessagePayload::Block(block.clone()) }).await;
+    }
+    // held ones go out from recv, which the node is always sitting in
+    tokio::spawn(async move { byzantine.recv().await });
+
+    let wait = |ms| std::time::Duration::from_millis(ms);
+    assert!(tokio::time::timeout(wait(100), other.recv()).await.is_ok());
+    assert!(tokio::time::timeout(wait(500), leader.recv()).await.is_err(), "leader got it straight away");
+    assert!(tokio::time::timeout(wait(1000), leader.recv()).await.is_ok());
+}
+
+/*
+    Node 4 misbehaves, a different way in each cluster. The three honest
+    nodes still have to commit, agree on every commit, and never let two
+    headers from the same author and round into the log
+*/
+#[tokio::test]
+async fn honest_nodes_agree_with_a_byzantine_peer() {
+    let behaviors = ["equivocate", "withhold-votes", "bad-signatures", "vote-everything", "delay-leader", "silent"];
+    assert!("lie-about-everything".parse::<Behavior>().is_err());
+
+    let runs = behaviors.iter().map(|name| async move {
+        // delay-leader goes after leaders it can name ahead of time - bullshark's steady anchors
+        let mode = if *name == "delay-leader" { bullshark(false) } else { ConsensusMode::default() };
+        let config = SimulationConfig {
+            latency_ms: (5, 20),
+            packet_loss_rate: 0.0,
+            node_churn: 0.0,
+            byzantine_nodes: vec![(4, name.parse().unwrap())],
+            mode,
+            ..Default::default()
+        };
+        let mut cluster = start_cluster(Simulator::new(config), 4).await;
+        cluster.run(std::time::Duration::from_millis(2000)).await;
+
+        let shortest = assert_logs_agree(&cluster, 1..=3, name).await;
+        assert!(shortest >= 2, "{}: honest nodes stopped committing", name);
+
+        let mut seen = std::collections::HashSet::new();
+        let sub_dags: Vec<CommittedSubDag> = cluster.consensus(1).unwrap().subscribe(0).take(shortest as usize).collect().await;
+        for block in sub_dags.iter().flat_map(|sub_dag| &sub_dag.blocks) {
+            assert!(seen.insert((block.round, block.author)), "{}: two headers from {} in round {}", name, block.author, block.round);
+        }
+    });
+    futures::future::join_all(runs).await;
+}
+
+/*
+    Half of everything (acks too) is lost on the way. Every message still
+    arrives exactly once, and the sender ends up with nothing left to resend
+*/
+#[tokio::test(start_paused = true)]
+async fn reliable_delivery_over_lossy_links() {
+    let config = SimulationConfig { latency_ms: (5, 20), packet_loss_rate: 0.5, seed: 7, ..Default::default() };
+    let sim = Simulator::new(config);
+    let peers = vec![1, 2];
+    let mut sender = ReliableTransport::new(sim.connect(1).await, 1, peers.clone(), ReliableConfig::default(), sim.random_u64());
+    let mut receiver = ReliableTransport::new(sim.connect(2).await, 2, peers, ReliableConfig::default(), sim.random_u64());
+
+    let mut sending = tokio::spawn(async move {
+        for i in 0..100u8 {
+            sender.send(NetworkMsg { from: 1, to: 2, payload: MessagePayload::FetchCertificates(vec![[i; 32]]) }).await;
+        }
+        // acks only get read in recv
+        while sender.unacked() > 0 {
+            let _ = tokio::time::timeout(std::time::Duration::from_millis(50), sender.recv()).await;
+        }
+    });
+
+    // keep acking until the sender has heard back about everything
+    let mut received = Vec::new();
+    loop {
+        tokio::select! {
+            done = &mut sending => break done.unwrap(),
+            Some(msg) = receiver.recv() => match msg.payload {
+                MessagePayload::FetchCertificates(hashes) => received.push(hashes[0][0]),
+                other => panic!("wrong payload {:?}", other),
+            },
+        }
+    }
+
+    received.sort();
+    assert_eq!(received, (0..100).collect::<Vec<u8>>());
+}
+
+// a third of the messages lost - with resends the nodes don't need history fetches to keep going
+#[tokio::test]
+async fn nodes_commit_over_lossy_links_with_reliable_delivery() {
+    let config = SimulationConfig {
+        latency_ms: (5, 20),
+        packet_loss_rate: 0.3,
+        node_churn: 0.0,
+        reliable: Some(ReliableConfig::default()),
+        ..Default::default()
+    };
+    let mut cluster = start_cluster(Simulator::new(config), 4).await;
+    cluster.run(std::time::Duration::from_secs(3)).await;
+
+    let shortest = assert_logs_agree(&cluster, 1..=4, "lossy").await;
+    assert!(shortest >= 2, "only {} commits", shortest);
+}
+
+/*
+    Node 1 has a 100 kB/s upload, everyone else 1 MB/s both ways. A 100 kB
+    block takes a second to leave node 1 and a tenth to get into node 2, and
+    the vote node 1 sends right after waits its turn behind it. Node 2's
+    vote is tiny and goes straight through
+*/
+#[tokio::test(start_paused = true)]
+async fn big_messages_queue_on_slow_links() {
+    let config = SimulationConfig {
+        latency_ms: (10, 11),
+        packet_loss_rate: 0.0,
+        bandwidth: Some(Bandwidth::symmetric(1_000_000)),
+        node_bandwidth: vec![(1, Bandwidth { upload: 100_000, download: 1_000_000 })],
+        ..Default::default()
+    };
+    let sim = Simulator::new(config);
+    let (one, mut two, mut three) = (sim.connect(1).await, sim.connect(2).await, sim.connect(3).await);
+    let ms = std::time::Duration::from_millis;
+
+    let block = Block::new(vec![Transaction::new("x".repeat(100_000))], vec![], 1, 0);
+    let block = NetworkMsg { from: 1, to: 2, payload: MessagePayload::Block(block) };
+    let keys = signing_keys(&make_validator_set(3));
+    let vote = |from, to| NetworkMsg { from, to, payload: MessagePayload::Vote(Vote::new([0u8; 32], 0, &keys[&from])) };
+    assert!(block.size_bytes() > 100_000 && vote(1, 3).size_bytes() < 200);
+
+    let start = tokio::time::Instant::now();
+    one.send(block).await;
+    one.send(vote(1, 3)).await;
+    two.send(vote(2, 3)).await;
+
+    assert_eq!(three.recv().await.unwrap().from, 2);
+    assert!(start.elapsed() < ms(20));
+    assert_eq!(three.recv().await.unwrap().from, 1);
+    let vote_at = start.elapsed();
+    assert!(vote_at > ms(1000) && vote_at < ms(1020), "vote after {:?}", vote_at);
+    assert!(matches!(two.recv().await.unwrap().payload, MessagePayload::Block(_)));
+    let block_at = start.elapsed();
+    assert!(block_at > ms(1100) && block_at < ms(1120), "block after {:?}", block_at);
+}
+
+/*
+    Where the leader is matters. Three nodes in us-east and one in Sydney:
+    the three form quorums among themselves long before Sydney's header
+    shows up, so its rounds never get a leader committed. Spread over four
+    regions every round waits on a cross-ocean quorum and far less commits
+*/
+#[test]
+fn leader_placement_changes_commits() {
+    fn leaders(topology: Topology) -> Vec<u32> {
+        let config = SimulationConfig {
+            packet_loss_rate: 0.0,
+            node_churn: 0.0,
+            seed: 3,
+            virtual_time: true,
+            topology: Some(topology),
+            ..Default::default()
+        };
+        config.runtime().unwrap().block_on(async move {
+            let mut cluster = start_cluster(Simulator::new(config), 4).await;
+            cluster.run(std::time::Duration::from_millis(300)).await;
+
+            let consensus = cluster.consensus(1).unwrap();
+            let count = consensus.commit_count().await as usize;
+            consensus.subscribe(0).take(count)
+                .map(|sub_dag| sub_dag.blocks.iter().find(|block| block.hash == sub_dag.leader).unwrap().author)
+                .collect().await
+        })
+    }
+
+    let one_remote = leaders(Topology::aws_one_remote(1..=4, 4));
+    let spread = leaders(Topology::aws_spread(1..=4));
+    assert!(one_remote.len() > 10 && one_remote.len() > spread.len() * 3, "{} vs {} commits", one_remote.len(), spread.len());
+    assert!(!one_remote.contains(&4));
+    for leader in 1..=3 {
+        assert!(one_remote.contains(&leader));
+    }
+}
+
+// deletes the file when the test is done with it, passed or not
+struct TempFile(std::path::PathBuf);
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.0);
+    }
+}
+
+/*
+    Record a lossy run to a file, then replay what node 2 got to a node 2
+    on its own. Nothing but the recorded deliveries goes in, and it has to
+    build the same headers and commit exactly what it committed in the run
+*/
+#[test]
+fn traced_run_replays_to_one_node() {
+    let file = TempFile(std::env::temp_dir().join(format!("narwhal-trace-{}", std::process::id())));
+    let config = SimulationConfig {
+        latency_ms: (5, 50),
+        packet_loss_rate: 0.05,
+        node_churn: 0.0,
+        seed: 99,
+        virtual_time: true,
+        ..Default::default()
+    };
+    // (sequence, digest) per commit
+    type Commits = Vec<(u64, Hash)>;
+    let path = file.0.clone();
+    let recorded: Commits = config.runtime().unwrap().block_on(async move {
+        assert!(Simulator::new(config.clone()).with_trace("/no/such/dir/trace").is_err());
+        let sim = Simulator::new(config).with_trace(&path).unwrap();
+        let mut cluster = start_cluster(sim.clone(), 4).await;
+        cluster.run(std::time::Duration::from_secs(2)).await;
+        sim.flush_trace().unwrap();
+
+        let consensus = cluster.consensus(2).unwrap();
+        let count = consensus.commit_count().await as usize;
+        consensus.subscribe(0).take(count).map(|sub_dag| (sub_dag.sequence, sub_dag.digest)).collect().await
+    });
+    assert!(recorded.len() >= 3, "only {} commits", recorded.len());
+
+    let trace = Trace::read(&file.0).unwrap();
+    assert_eq!(trace.seed, 99);
+    let count = |kind| trace.events.iter().filter(|event| event.kind == kind).count();
+    assert!(count(TraceKind::Lost) > 0);
+    // whatever didn't arrive by the end is still in flight
+    assert!(count(TraceKind::Deliver) + count(TraceKind::Lost) <= count(TraceKind::Send));
+    assert!(trace.events.windows(2).all(|pair| pair[0].at <= pair[1].at));
+
+    let runtime = SimulationConfig { virtual_time: true, ..Default::default() }.runtime().unwrap();
+    let replayed: Commits = runtime.block_on(async move {
+        let vset = make_validator_set(4);
+        let key = signing_keys(&vset).remove(&2).unwrap();
+        let consensus = ConsensusHandle::new(vset).with_keys([key]);
+        let (transport, mut script) = scripted(vec![1, 2, 3, 4]);
+        let node = tokio::spawn(Node::with_consensus(2, transport, consensus.clone()).run_node());
+        replay(&trace, 2, &script).await;
+        // let the last commit tick go by
+        tokio::time::sleep(std::time::Duration::from_millis(500)).await;
+        node.abort();
+
+        // same headers out, and a vote for every header it voted for in the run
+        let sent = script.drain_sent();
+        let headers = |msgs: &mut dyn Iterator<Item = &NetworkMsg>| msgs
+            .filter_map(|msg| match &msg.payload { MessagePayload::Block(block) if block.author == 2 => Some(block.hash), _ => None })
+            .collect::<std::collections::HashSet<_>>();
+        let votes = |msgs: &mut dyn Iterator<Item = &NetworkMsg>| msgs
+            .filter_map(|msg| match &msg.payload { MessagePayload::Vote(vote) => Some(vote.block_hash), _ => None })
+            .collect::<std::collections::HashSet<_>>();
+        let recorded_headers = headers(&mut trace.sent_by(2).map(|event| &event.msg));
+        assert!(!recorded_headers.is_empty());
+        assert!(recorded_headers.is_subset(&headers(&mut sent.iter())));
+        assert!(votes(&mut trace.sent_by(2).map(|event| &event.msg)).is_subset(&votes(&mut sent.iter())));
+
+        let count = consensus.commit_count().await as usize;
+        consensus.subscribe(0).take(count).map(|sub_dag| (sub_dag.sequence, sub_dag.digest)).collect().await
+    });
+    assert_eq!(replayed, recorded);
+}
+
+// a peer that isn't listening yet gets the message once it comes up
+#[tokio::test]
+async fn tcp_delivers_after_reconnect() {
+    let localhost = "127.0.0.1:0".parse().unwrap();
+    let a = TcpNetwork::bind(1, localhost, TcpConfig::default()).await.unwrap();
+    let a_addr = a.local_addr().unwrap();
+    // grab a free port for b and let it go again
+    let b_addr = TcpNetwork::bind(2, localhost, TcpConfig::default()).await.unwrap().local_addr().unwrap();
+
+    let mut a_net = a.start([(2, b_addr)].into());
+    let vset = make_validator_set(2);
+    let vote = Vote::new([1u8; 32], 0, &signing_keys(&vset)[&1]);
+    a_net.send(NetworkMsg { from: 1, to: 2, payload: MessagePayload::Vote(vote) }).await;
+
+    tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+    let b = TcpNetwork::bind(2, b_addr, TcpConfig::default()).await.unwrap();
+    let mut b_net = b.start([(1, a_addr)].into());
+
+    let within = |secs| std::time::Duration::from_secs(secs);
+    let msg = tokio::time::timeout(within(5), b_net.recv()).await.unwrap().unwrap();
+    assert_eq!((msg.from, msg.to), (1, 2));
+    assert!(matches!(msg.payload, MessagePayload::Vote(v) if v.verify(&vset)));
+
+    b_net.broadcast(2, MessagePayload::FetchCertificates(vec![[3u8; 32]])).await;
+    let reply = tokio::time::timeout(within(5), a_net.recv()).await.unwrap().unwrap();
+    assert!(matches!(reply.payload, MessagePayload::FetchCertificates(h) if h == vec![[3u8; 32]]));
+}
+
+// a connection speaks for the peer that dialed it and nobody else, and a dropped transport stops listening
+#[tokio::test]
+async fn tcp_binds_connections_to_their_peer() {
+    use tokio::io::AsyncWriteExt;
+
+    let localhost = "127.0.0.1:0".parse().unwrap();
+    let a = TcpNetwork::bind(1, localhost, TcpConfig::default()).await.unwrap();
+    let a_addr = a.local_addr().unwrap();
+    let unused = "127.0.0.1:9".parse().unwrap();
+    let mut a_net = a.start([(2, unused), (3, unused)].into());
+
+    let frame = |from, tag| {
+        let bytes = encode_msg(&NetworkMsg { from, to: 1, payload: MessagePayload::FetchCertificates(vec![[tag; 32]]) });
+        [(bytes.len() as u32).to_be_bytes().to_vec(), bytes].concat()
+    };
+    // says it's 2, then sends as 3 - dropped along with the connection
+    let mut liar = tokio::net::TcpStream::connect(a_addr).await.unwrap();
+    liar.write_all(&[2u32.to_be_bytes().to_vec(), frame(3, 0)].concat()).await.unwrap();
+    // not a peer at all
+    let mut stranger = tokio::net::TcpStream::connect(a_addr).await.unwrap();
+    stranger.write_all(&[7u32.to_be_bytes().to_vec(), frame(7, 0)].concat()).await.unwrap();
+    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+
+    let mut honest = tokio::net::TcpStream::connect(a_addr).await.unwrap();
+    honest.write_all(&[3u32.to_be_bytes().to_vec(), frame(3, 1)].concat()).await.unwrap();
+    let msg = tokio::time::timeout(std::time::Duration::from_secs(5), a_net.recv()).await.unwrap().unwrap();
+    assert!(matches!(msg.payload, MessagePayload::FetchCertificates(h) if h == vec![[1u8; 32]]));
+    assert_eq!(msg.from, 3);
+
+    drop(a_net);
+    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+    assert!(tokio::net::TcpStream::connect(a_addr).await.is_err(), "still listening after the transport dropped");
+}
+
+// same Node code, real sockets
+#[tokio::test]
+async fn nodes_commit_over_tcp() {
+    let vset = make_validator_set(4);
+    let localhost = "127.0.0.1:0".parse().unwrap();
+
+    let mut bound = Vec::new();
+    for id in 1..=4 {
+        bound.push((id, TcpNetwork::bind(id, localhost, TcpConfig::default()).await.unwrap()));
+    }
+    let peers: HashMap<_, _> = bound.iter()
+        .map(|(id, net)| (*id, net.local_addr().unwrap()))
+        .collect();
+
+    let keys = signing_keys(&vset);
+    let mut tasks = Vec::new();
+    let mut commits = None;
+    for (id, net) in bound {
+        let consensus = ConsensusHandle::new(vset.clone()).with_keys([keys[&id].clone()]);
+        if id == 1 {
+            commits = Some(consensus.subscribe(0));
+        }
+        let node = Node::with_consensus(id, net.start(peers.clone()), consensus);
+        tasks.push(tokio::spawn(node.run_node()));
+    }
+
+    let mut commits = commits.unwrap();
+    for sequence in 0..2 {
+        let sub_dag = tokio::time::timeout(std::time::Duration::from_secs(10), commits.next())
+            .await
+            .expect("no commits over tcp")
+            .unwrap();
+        assert_eq!(sub_dag.sequence, sequence);
+    }
+
+    for task in tasks {
+        task.abort();
+    }
+}
+
+async fn sent(script: &mut Script) -> NetworkMsg {
+    tokio::time::timeout(std::time::Duration::from_secs(5), script.next_sent()).await.unwrap().unwrap()
+}
+
+/*
+    scripted transport: the test plays the other three validators by hand
+    and watches exactly what node 1 puts on the wire
+*/
+#[tokio::test]
+async fn scripted_transport_drives_a_node() {
+    let vset = make_validator_set(4);
+    let (transport, mut script) = scripted(vec![1, 2, 3, 4]);
+    let keys = signing_keys(&vset);
+    let node = Node::with_consensus(1, transport, ConsensusHandle::new(vset.clone()).with_keys([keys[&1].clone()]));
+    let task = tokio::spawn(node.run_node());
+
+    // round 0 header goes to the three peers
+    let mut header = None;
+    for _ in 0..3 {
+        match sent(&mut script).await.payload {
+            MessagePayload::Block(block) => header = Some(block),
+            other => panic!("expected the header, got {:?}", other),
+        }
+    }
+    let header = header.unwrap();
+    assert_eq!((header.author, header.round), (1, 0));
+
+    // two more votes make 2f+1 with node 1's own - certificate goes to everyone
+    for voter in [2, 3] {
+        script.inject(NetworkMsg { from: voter, to: 1, payload: MessagePayload::Vote(Vote::new(header.hash, 0, &keys[&voter])) });
+    }
+    for _ in 0..3 {
+        match sent(&mut script).await.payload {
+            MessagePayload::Certificate(cert) => assert!(cert.verify(&vset).is_ok()),
+            other => panic!("expected the certificate, got {:?}", other),
+        }
+    }
+
+    // someone else's header - the vote goes back to its author only
+    let other = Block::new(vec![], vec![], 2, 0);
+    script.inject(NetworkMsg { from: 2, to: 1, payload: MessagePayload::Block(other.clone()) });
+    let vote = sent(&mut script).await;
+    assert_eq!(vote.to, 2);
+    assert!(matches!(vote.payload, MessagePayload::Vote(v) if v.block_hash == other.hash && v.voter == 1));
+    assert!(script.drain_sent().is_empty());
+
+    // nothing more can come in once the script is gone - the node stops instead of ticking forever
+    drop(script);
+    tokio::time::timeout(std::time::Duration::from_secs(5), task).await
+        .expect("node still running after its transport closed")
+        .unwrap();
+}